@@ -0,0 +1,302 @@
+use opencv::{
+    core,
+    prelude::*,
+    videoio,
+    Result,
+};
+use std::fs;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::source::FrameSource;
+
+// Frame delay used when the container doesn't report a frame rate
+const DEFAULT_FPS: f64 = 10.0;
+
+// What to do once the last frame of an animation has been shown
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Playback {
+    Loop,  // Start over from the first frame, forever
+    Once,  // Stop and keep showing the last frame
+    Exit,  // Stop and quit
+}
+
+// Which frames of an animation get shown
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Frames {
+    pub step: u32,         // Show every step-th frame
+    pub start: u32,        // First frame, counting from 0
+    pub end: Option<u32>,  // Stop before this frame, None plays to the end
+    // Skip frames that differ from the last one drawn by at most this much
+    // per channel on average (0-255), None draws every frame
+    pub skip_still: Option<f64>,
+    pub collapse_still: bool,  // and don't wait out the skipped frames' delays either
+}
+
+impl Default for Frames {
+    fn default() -> Frames {
+        return Frames { step: 1, start: 0, end: None, skip_still: None, collapse_still: false };
+    }
+}
+
+// Parse a --frame-range value, "start:end" with either side optional.
+// The end is exclusive, so "0:10" is the first ten frames.
+pub fn parse_range(value: &str) -> Result<(u32, Option<u32>), String> {
+    let invalid = || format!("expected start:end (eg 10:50 or 10:), got {}", value);
+    let (start, end) = value.split_once(':').ok_or_else(invalid)?;
+    let start = match start.trim() {
+        "" => 0,
+        start => start.parse().map_err(|_| invalid())?,
+    };
+    let end = match end.trim() {
+        "" => None,
+        end => Some(end.parse::<u32>().map_err(|_| invalid())?),
+    };
+    if end.is_some_and(|end| end <= start) {
+        return Err(format!("the range {} is empty, the end must come after the start", value));
+    }
+    return Ok((start, end));
+}
+
+// Make sure the selected frames exist. Containers that don't report a frame
+// count can't be checked up front, playback just ends early for those.
+pub fn check_frames(path: &str, frames: Frames) -> Result<(), String> {
+    if frames.start == 0 && frames.end.is_none() {
+        return Ok(());
+    }
+    let count = videoio::VideoCapture::from_file(path, videoio::CAP_ANY)
+        .and_then(|capture| capture.get(videoio::CAP_PROP_FRAME_COUNT))
+        .unwrap_or(0.0);
+    if count <= 0.0 {
+        return Ok(());
+    }
+    let count = count as u32;
+    if frames.start >= count {
+        return Err(format!("--frame-range starts at frame {} but {} only has {} frames (0-{})", frames.start, path, count, count - 1));
+    }
+    if let Some(end) = frames.end.filter(|&end| end > count) {
+        return Err(format!("--frame-range ends at frame {} but {} only has {} frames", end, path, count));
+    }
+    return Ok(());
+}
+
+// Files we hand to VideoCapture instead of imread
+pub fn is_animation(path: &str) -> bool {
+    let ext = match path.rsplit_once('.') {
+        Some((_, ext)) => ext.to_ascii_lowercase(),
+        None => return false,
+    };
+    matches!(ext.as_str(), "gif" | "mp4" | "mkv" | "webm" | "avi" | "mov")
+}
+
+// Read the loop count from a GIF's NETSCAPE2.0 application extension.
+// Some(0) means loop forever, Some(n) means repeat n more times. GIFs without
+// the extension (and anything that isn't a GIF) play once, which is None.
+pub fn gif_loop_count(path: &str) -> Option<u16> {
+    let bytes = fs::read(path).ok()?;
+    if !bytes.starts_with(b"GIF8") {
+        return None;
+    }
+    let marker = b"NETSCAPE2.0";
+    let start = bytes.windows(marker.len()).position(|w| w == marker)? + marker.len();
+    // Sub-block layout: size (3), id (1), loop count (u16 little endian)
+    match bytes.get(start..start + 4) {
+        Some([3, 1, lo, hi]) => Some(u16::from_le_bytes([*lo, *hi])),
+        _ => None,
+    }
+}
+
+// Work out how many passes to make over the frames. None means forever.
+fn passes(path: &str, playback: Option<Playback>) -> Option<u32> {
+    match playback {
+        Some(Playback::Loop) => None,
+        Some(Playback::Once) | Some(Playback::Exit) => Some(1),
+        None => match gif_loop_count(path) {
+            Some(0) => None,
+            Some(n) => Some(n as u32 + 1),
+            None => Some(1),
+        },
+    }
+}
+
+// How far apart two frames are, the average difference of their channels.
+// Frames of different sizes are as far apart as can be.
+fn mean_difference(a: &Mat, b: &Mat) -> Result<f64> {
+    if a.size()? != b.size()? || a.typ() != b.typ() {
+        return Ok(f64::MAX);
+    }
+    let mut difference = Mat::default();
+    core::absdiff(a, b, &mut difference)?;
+    let sum = core::sum_elems(&difference)?;
+    let channels = difference.channels().min(4) as usize;
+    let total: f64 = (0..channels).map(|i| sum[i]).sum();
+    return Ok(total / (difference.total() * channels) as f64);
+}
+
+// Plays an animation one frame at a time at the clip's own pace, making
+// passes over the frames picked by `frames` the way `playback` says. Each
+// frame gets the clip's usual delay, so a step of n plays n times faster.
+// With skip_still, frames that look the same as the last one handed out are
+// passed over, and with collapse_still so is their delay.
+pub struct Player {
+    path: String,
+    passes: Option<u32>,  // None plays forever
+    frames: Frames,
+    pass: u32,
+    capture: Option<videoio::VideoCapture>,  // Open for the pass being played
+    position: u32,
+    delay: Duration,
+    read: u32,             // Frames read this pass
+    shown: u32,            // Frames handed out this pass
+    due: Option<Instant>,  // When the next frame is up
+    last: Mat,             // The last frame handed out
+    failed: bool,
+}
+
+impl Player {
+    pub fn new(path: &str, playback: Option<Playback>, frames: Frames) -> Player {
+        return Player {
+            path: path.to_string(),
+            passes: passes(path, playback),
+            frames,
+            pass: 0,
+            capture: None,
+            position: 0,
+            delay: Duration::ZERO,
+            read: 0,
+            shown: 0,
+            due: None,
+            last: Mat::default(),
+            failed: false,
+        };
+    }
+
+    // Re-open for every pass, seeking back to 0 isn't reliable for GIFs
+    fn start_pass(&mut self) -> Result<()> {
+        let mut capture = videoio::VideoCapture::from_file(&self.path, videoio::CAP_ANY)?;
+        if !capture.is_opened()? {
+            return Err(opencv::Error::new(core::StsError, format!("Could not open animation: {}", self.path)));
+        }
+        let fps = capture.get(videoio::CAP_PROP_FPS)?;
+        self.delay = Duration::from_secs_f64(1.0 / if fps > 0.0 { fps } else { DEFAULT_FPS });
+
+        // Seeking is quick for videos but not reliable for GIFs, which get
+        // stepped through instead
+        self.position = 0;
+        if self.frames.start > 0 && !self.path.to_ascii_lowercase().ends_with(".gif")
+            && capture.set(videoio::CAP_PROP_POS_FRAMES, self.frames.start as f64)? {
+            self.position = self.frames.start;
+        }
+        while self.position < self.frames.start && capture.grab()? {
+            self.position += 1;
+        }
+        self.read = 0;
+        self.shown = 0;
+        self.capture = Some(capture);
+        return Ok(());
+    }
+
+    // The next frame of this pass, None at the end of it
+    fn read_frame(&mut self) -> Result<Option<Mat>> {
+        let capture = match self.capture.as_mut() {
+            Some(capture) => capture,
+            None => return Ok(None),
+        };
+        let mut frame = Mat::default();
+        if self.frames.end.is_some_and(|end| self.position >= end) || !capture.read(&mut frame)? || frame.empty() {
+            return Ok(None);
+        }
+        self.position += 1;
+        // grab() skips a frame without converting it
+        for _ in 1..self.frames.step {
+            if !capture.grab()? {
+                break;
+            }
+            self.position += 1;
+        }
+        self.read += 1;
+        return Ok(Some(frame));
+    }
+
+    // Sleep out what's left of the last frame's delay
+    fn wait_turn(&mut self) {
+        if let Some(rest) = self.due.and_then(|due| due.checked_duration_since(Instant::now())) {
+            thread::sleep(rest);
+        }
+        self.due = Some(Instant::now() + self.delay);
+    }
+
+    fn next(&mut self) -> Result<Option<Mat>> {
+        loop {
+            if self.capture.is_none() {
+                if self.passes.is_some_and(|passes| self.pass >= passes) {
+                    return Ok(None);
+                }
+                self.start_pass()?;
+            }
+            let frame = match self.read_frame()? {
+                Some(frame) => frame,
+                None => {
+                    if self.read == 0 {
+                        return Err(opencv::Error::new(core::StsError, format!("No frames in animation: {}", self.path)));
+                    }
+                    // Every frame matched the one up already, and so will
+                    // every pass after this one
+                    if self.shown == 0 {
+                        return Ok(None);
+                    }
+                    self.capture = None;
+                    self.pass += 1;
+                    continue;
+                },
+            };
+            // Compared with the last frame handed out, so a slow fade still
+            // comes through once it has added up
+            let still = match self.frames.skip_still {
+                Some(threshold) if !self.last.empty() => mean_difference(&frame, &self.last)? <= threshold,
+                _ => false,
+            };
+            if still {
+                if !self.frames.collapse_still {
+                    self.wait_turn();
+                }
+                continue;
+            }
+            self.wait_turn();
+            self.last = frame.try_clone()?;
+            self.shown += 1;
+            return Ok(Some(frame));
+        }
+    }
+}
+
+impl FrameSource for Player {
+    fn next_frame(&mut self) -> Option<Result<Mat>> {
+        if self.failed {
+            return None;
+        }
+        let next = self.next();
+        self.failed = next.is_err();
+        return next.transpose();
+    }
+
+    fn path(&self) -> Option<String> {
+        return Some(self.path.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_ranges() {
+        assert_eq!(parse_range("10:50"), Ok((10, Some(50))));
+        assert_eq!(parse_range(":50"), Ok((0, Some(50))));
+        assert_eq!(parse_range("10:"), Ok((10, None)));
+        assert!(parse_range("50:10").is_err());
+        assert!(parse_range("5:5").is_err());
+        assert!(parse_range("10").is_err());
+        assert!(parse_range("a:b").is_err());
+    }
+}
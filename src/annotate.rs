@@ -0,0 +1,116 @@
+use opencv::{
+    core,
+    imgproc,
+    prelude::*,
+    Result,
+};
+
+use crate::palette;
+
+// What the shapes are drawn in when no color is given
+const DEFAULT_COLOR: (u8, u8, u8) = (255, 0, 0);
+
+// Lines are this fraction of the image's longest side thick, so they still
+// show once the image is shrunk to the terminal
+const LINE_FRACTION: i32 = 150;
+
+// Something to draw on the image, in pixels of the source image
+#[derive(Clone, Debug, PartialEq)]
+pub enum Shape {
+    Rect(i32, i32, i32, i32),  // Left, top, width, height
+    Circle(i32, i32, i32),     // Center and radius
+    Text(String, i32, i32),    // Bottom left corner of the text
+}
+
+pub type Annotation = (Shape, (u8, u8, u8));
+
+// --rect value, "x,y,w,h" with an optional ":HEX" color
+pub fn parse_rect(value: &str) -> Result<Annotation, String> {
+    let (place, color) = split_color(value)?;
+    match numbers(place, "x,y,w,h")?[..] {
+        [x, y, w, h] if w > 0 && h > 0 => Ok((Shape::Rect(x, y, w, h), color)),
+        [_, _, _, _] => Err("the rectangle has to be at least 1x1".to_string()),
+        _ => Err(format!("expected x,y,w,h: {}", place)),
+    }
+}
+
+// --circle value, "x,y,r" with an optional ":HEX" color
+pub fn parse_circle(value: &str) -> Result<Annotation, String> {
+    let (place, color) = split_color(value)?;
+    match numbers(place, "x,y,r")?[..] {
+        [x, y, r] if r > 0 => Ok((Shape::Circle(x, y, r), color)),
+        [_, _, _] => Err("the radius has to be at least 1".to_string()),
+        _ => Err(format!("expected x,y,r: {}", place)),
+    }
+}
+
+// --text value, "text@x,y" with an optional ":HEX" color after the position
+pub fn parse_text(value: &str) -> Result<Annotation, String> {
+    let (text, place) = value.rsplit_once('@').ok_or_else(|| format!("expected text@x,y: {}", value))?;
+    let (place, color) = split_color(place)?;
+    match numbers(place, "x,y")?[..] {
+        [x, y] if !text.is_empty() => Ok((Shape::Text(text.to_string(), x, y), color)),
+        [_, _] => Err("the text is empty".to_string()),
+        _ => Err(format!("expected text@x,y: {}", value)),
+    }
+}
+
+// Take the color off the end, "10,20:#00ff00"
+fn split_color(value: &str) -> Result<(&str, (u8, u8, u8)), String> {
+    match value.rsplit_once(':') {
+        Some((place, hex)) => Ok((place, palette::parse_hex(hex.trim())?)),
+        None => Ok((value, DEFAULT_COLOR)),
+    }
+}
+
+fn numbers(value: &str, expected: &str) -> Result<Vec<i32>, String> {
+    return value
+        .split(',')
+        .map(|n| n.trim().parse().map_err(|_| format!("expected {}: {}", expected, value)))
+        .collect();
+}
+
+// A copy of an 8 bit BGR(A) image with the annotations drawn on it in order.
+// They're opaque on images with an alpha channel.
+pub fn draw(image: &Mat, annotations: &[Annotation]) -> Result<Mat> {
+    let mut drawn = image.try_clone()?;
+    let thickness = (image.cols().max(image.rows()) / LINE_FRACTION).max(1);
+    for (shape, (r, g, b)) in annotations {
+        let color = core::Scalar::new(*b as f64, *g as f64, *r as f64, 255.0);
+        match shape {
+            Shape::Rect(x, y, w, h) => {
+                imgproc::rectangle(&mut drawn, core::Rect::new(*x, *y, *w, *h), color, thickness, imgproc::LINE_AA, 0)?;
+            },
+            Shape::Circle(x, y, radius) => {
+                imgproc::circle(&mut drawn, core::Point::new(*x, *y), *radius, color, thickness, imgproc::LINE_AA, 0)?;
+            },
+            Shape::Text(text, x, y) => {
+                // Letters are about 22 pixels tall at scale 1, this makes them
+                // about a fourteenth of the longest side
+                let scale = thickness as f64 / 2.0;
+                imgproc::put_text(
+                    &mut drawn, text, core::Point::new(*x, *y), imgproc::FONT_HERSHEY_SIMPLEX,
+                    scale, color, thickness, imgproc::LINE_AA, false,
+                )?;
+            },
+        }
+    }
+    return Ok(drawn);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn annotation_values() {
+        assert_eq!(parse_rect("10,20,30,40"), Ok((Shape::Rect(10, 20, 30, 40), DEFAULT_COLOR)));
+        assert_eq!(parse_circle("5, 5, 3:#00ff00"), Ok((Shape::Circle(5, 5, 3), (0, 255, 0))));
+        // The last @ starts the position, so the text can have its own
+        assert_eq!(parse_text("me@home@4,8:fff"), Ok((Shape::Text("me@home".to_string(), 4, 8), (255, 255, 255))));
+        assert!(parse_rect("10,20,0,40").is_err());
+        assert!(parse_circle("5,5").is_err());
+        assert!(parse_text("4,8").is_err());
+        assert!(parse_text("@4,8").is_err());
+    }
+}
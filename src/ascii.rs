@@ -0,0 +1,92 @@
+use opencv::{
+    core,
+    imgproc,
+    prelude::*,
+    Result,
+};
+use crate::filters;
+use crate::Placement;
+
+// Darkest to brightest, drawn on a dark terminal background
+pub const DEFAULT_CHARSET: &str = " .:-=+*#%@";
+
+// Turn the image into one character per cell by brightness. Equalizing first
+// spreads typical photos, which sit mostly in the middle tones, across the
+// whole ramp. Fully transparent pixels become spaces. `weights` are the
+// red, green and blue shares of the brightness.
+pub fn build_glyphs(
+    image: &Mat,
+    dimensions: (i32, i32),
+    charset: &[char],
+    equalize: bool,
+    weights: (f32, f32, f32),
+) -> Result<Vec<Vec<char>>> {
+    let mut resized = Mat::default();
+    imgproc::resize(image, &mut resized, core::Size::new(dimensions.0, dimensions.1), 0.0, 0.0, imgproc::INTER_AREA)?;
+
+    let has_alpha = resized.channels() == 4;
+    let mut gray = filters::luminance(&resized, weights)?;
+    if equalize {
+        let mut equalized = Mat::default();
+        imgproc::equalize_hist(&gray, &mut equalized)?;
+        gray = equalized;
+    }
+
+    let mut glyphs = Vec::with_capacity(gray.rows() as usize);
+    for r in 0..gray.rows() {
+        let mut row = Vec::with_capacity(gray.cols() as usize);
+        for c in 0..gray.cols() {
+            if has_alpha && resized.at_2d::<core::Vec4b>(r, c)?[3] == 0 {
+                row.push(' ');
+                continue;
+            }
+            row.push(glyph(*gray.at_2d::<u8>(r, c)?, charset));
+        }
+        glyphs.push(row);
+    }
+    return Ok(glyphs);
+}
+
+// Pick the character for a brightness, spreading 0-255 evenly over the ramp
+fn glyph(value: u8, charset: &[char]) -> char {
+    if charset.is_empty() {
+        return ' ';
+    }
+    let index = (value as usize * charset.len() / 256).min(charset.len() - 1);
+    return charset[index];
+}
+
+// Append the lines of characters
+pub fn write_glyphs(out: &mut String, glyphs: &[Vec<char>], placement: Placement) {
+    for (r, row) in glyphs.iter().enumerate() {
+        placement.start_row(out, r);
+        out.extend(row.iter());
+        placement.end_row(out);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode::decode_glyphs;
+
+    #[test]
+    fn brightness_to_glyph() {
+        let ramp: Vec<char> = DEFAULT_CHARSET.chars().collect();
+        assert_eq!(glyph(0, &ramp), ' ');
+        assert_eq!(glyph(255, &ramp), '@');
+        assert_eq!(glyph(128, &ramp), '+');
+        assert_eq!(glyph(200, &['a', 'b']), 'b');
+        assert_eq!(glyph(10, &[]), ' ');
+    }
+
+    #[test]
+    fn glyphs_round_trip() {
+        let glyphs = vec![vec!['@', '.'], vec![' ', '+']];
+        for placement in [Placement::Flow, Placement::Plain, Placement::At(0, 0)] {
+            let mut out = String::new();
+            write_glyphs(&mut out, &glyphs, placement);
+            assert_eq!(decode_glyphs(&out), vec!["@.", " +"]);
+        }
+    }
+}
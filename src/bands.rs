@@ -0,0 +1,122 @@
+use opencv::{
+    core,
+    imgproc,
+    prelude::*,
+    Result,
+};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+
+// Rows of the reduced image worked out at a time. Only the source rows that
+// shrink into them are ever in memory.
+const BAND_ROWS: i32 = 16;
+
+// What a binary PGM (P5) or PPM (P6) header says about the pixels after it
+#[derive(Debug, PartialEq)]
+struct Header {
+    channels: i32,
+    width: i32,
+    height: i32,
+}
+
+// Decode a binary PGM or PPM one band of rows at a time, shrinking each band
+// to its share of the size `reduce` picks for the image before reading the
+// next, so memory stays proportional to a band instead of the whole image.
+// None for every other format (and 16 bit files), those go through imread.
+pub fn load_reduced<F>(path: &str, reduce: F) -> Result<Option<Mat>>
+where
+    F: Fn((i32, i32)) -> (i32, i32),
+{
+    let io_error = |e: std::io::Error| opencv::Error::new(core::StsError, format!("Could not read {}: {}", path, e));
+    let mut reader = BufReader::new(File::open(path).map_err(io_error)?);
+    let header = match read_header(&mut reader) {
+        Some(header) => header,
+        None => return Ok(None),
+    };
+    let (width, height) = reduce((header.width, header.height));
+    let (width, height) = (width.clamp(1, header.width), height.clamp(1, header.height));
+    let typ = if header.channels == 1 { core::CV_8UC1 } else { core::CV_8UC3 };
+
+    let mut reduced = Mat::new_rows_cols_with_default(height, width, typ, core::Scalar::all(0.0))?;
+    let row_bytes = (width * header.channels) as usize;
+    let mut source_row = 0;
+    for top in (0..height).step_by(BAND_ROWS as usize) {
+        let bottom = (top + BAND_ROWS).min(height);
+        // The source rows that end up in rows top to bottom
+        let end = (bottom as i64 * header.height as i64 / height as i64) as i32;
+        let mut band = Mat::new_rows_cols_with_default(end - source_row, header.width, typ, core::Scalar::all(0.0))?;
+        reader.read_exact(band.data_bytes_mut()?).map_err(io_error)?;
+        source_row = end;
+
+        let mut shrunk = Mat::default();
+        imgproc::resize(&band, &mut shrunk, core::Size::new(width, bottom - top), 0.0, 0.0, imgproc::INTER_AREA)?;
+        let start = top as usize * row_bytes;
+        let shrunk = shrunk.data_bytes()?;
+        reduced.data_bytes_mut()?[start..start + shrunk.len()].copy_from_slice(shrunk);
+    }
+
+    // PPM stores RGB
+    if header.channels == 3 {
+        let mut bgr = Mat::default();
+        imgproc::cvt_color(&reduced, &mut bgr, imgproc::COLOR_RGB2BGR, 0)?;
+        reduced = bgr;
+    }
+    return Ok(Some(reduced));
+}
+
+// Magic, width, height and maximum value, separated by whitespace and
+// comments. Exactly one whitespace byte follows the maximum.
+fn read_header(reader: &mut impl BufRead) -> Option<Header> {
+    let channels = match token(reader)?.as_str() {
+        "P5" => 1,
+        "P6" => 3,
+        _ => return None,
+    };
+    let width: i32 = token(reader)?.parse().ok()?;
+    let height: i32 = token(reader)?.parse().ok()?;
+    let max: u32 = token(reader)?.parse().ok()?;
+    if width < 1 || height < 1 || max == 0 || max > 255 {
+        return None;
+    }
+    return Some(Header { channels, width, height });
+}
+
+// The next token, along with the whitespace byte that ends it
+fn token(reader: &mut impl BufRead) -> Option<String> {
+    let mut token = String::new();
+    let mut comment = false;
+    for byte in reader.bytes() {
+        let byte = byte.ok()?;
+        match byte {
+            b'\n' | b'\r' if comment => comment = false,
+            _ if comment => {},
+            b'#' if token.is_empty() => comment = true,
+            b if b.is_ascii_whitespace() => {
+                if !token.is_empty() {
+                    return Some(token);
+                }
+            },
+            b => token.push(b as char),
+        }
+    }
+    return None;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pnm_headers() {
+        let mut ppm = &b"P6\n# made by hand\n640 480\n255\n\xFF\x00"[..];
+        assert_eq!(read_header(&mut ppm), Some(Header { channels: 3, width: 640, height: 480 }));
+        // The pixels start right after the one whitespace byte
+        assert_eq!(ppm, b"\xFF\x00");
+
+        assert_eq!(read_header(&mut &b"P5 3 2 255 "[..]), Some(Header { channels: 1, width: 3, height: 2 }));
+        // Plain text and 16 bit files are left to OpenCV
+        assert_eq!(read_header(&mut &b"P3 3 2 255 "[..]), None);
+        assert_eq!(read_header(&mut &b"P6 3 2 65535 "[..]), None);
+        assert_eq!(read_header(&mut &b"\x89PNG"[..]), None);
+    }
+}
@@ -0,0 +1,341 @@
+use clap::{CommandFactory, Parser, Subcommand};
+use std::ffi::OsString;
+use std::time::Duration;
+
+use crate::filters::Cvd;
+use crate::palette::{self, ColorMode, Palette};
+use crate::Options;
+
+// How many dominant colors `imprev palette` lists by default
+const DEFAULT_PALETTE_COUNT: usize = 8;
+
+/// Preview images in the terminal
+#[derive(Parser, Debug)]
+#[command(name = "imprev", version, arg_required_else_help = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Draw an image, animation or FIFO stream (the default, `imprev <path>` works too)
+    Render(Box<Options>),
+    /// Print the size, format and frame details of an image
+    Info {
+        path: String,
+    },
+    /// Print the dominant colors of an image as swatches
+    Palette {
+        path: String,
+        /// How many colors to list
+        #[arg(short = 'n', long, default_value_t = DEFAULT_PALETTE_COUNT)]
+        count: usize,
+        /// Color depth of the swatches: 8, 16, 256, truecolor or auto
+        #[arg(long, default_value = "auto", value_parser = parse_colors)]
+        colors: ColorMode,
+    },
+    /// Draw two images side by side
+    Compare {
+        first: String,
+        second: String,
+        /// Color depth: 8, 16, 256, truecolor or auto
+        #[arg(long, default_value = "auto", value_parser = parse_colors)]
+        colors: ColorMode,
+        /// Snap both images to the colors in a file of hex colors
+        #[arg(long = "palette-file", value_name = "PATH", value_parser = Palette::load)]
+        palette: Option<Palette>,
+        /// Simulate a color vision deficiency on both images
+        #[arg(long, value_parser = Cvd::parse)]
+        cvd: Option<Cvd>,
+        /// Draw both at full size in one frame, the first left of this percentage of the width and the second right of it
+        #[arg(long, value_name = "PCT", value_parser = clap::value_parser!(u8).range(0..=100))]
+        split: Option<u8>,
+    },
+}
+
+// Parse the process arguments, treating `imprev [flags] <path>` as `imprev render`
+pub fn parse<I: IntoIterator<Item = OsString>>(args: I) -> Result<Cli, clap::Error> {
+    let mut cli = Cli::try_parse_from(with_default_command(args.into_iter().collect()))?;
+    if let Command::Render(options) = &mut cli.command {
+        // Tiling, charset and palette size options are no use on their own
+        options.tile |= options.tile_offset != (0, 0) || options.repeat_x.is_some() || options.repeat_y.is_some();
+        options.ascii |= options.charset.is_some();
+        options.adaptive_palette |= options.max_colors.is_some();
+        options.checkerboard |= options.checker_size.is_some() || options.checker_colors.is_some();
+        options.detect_faces |= options.cascade.is_some();
+        // Holding needs an end, animations that loop forever play once
+        options.play_once |= options.hold.is_some() && !options.play_exit;
+        // Redrawing in place and taking over the screen only work full screen
+        options.fullscreen |= !options.preview_at_cursor
+            && (options.refresh.is_some() || options.watch_dir.is_some() || options.home_only || options.scroll_region || options.clear_on_exit);
+    }
+    return Ok(cli);
+}
+
+// Insert "render" unless the first argument already picks a subcommand or asks for help
+fn with_default_command(mut args: Vec<OsString>) -> Vec<OsString> {
+    let first = match args.get(1).and_then(|arg| arg.to_str()) {
+        Some(first) => first.to_string(),
+        None => return args,
+    };
+    let command = Cli::command();
+    let is_subcommand = first == "help" || command.get_subcommands().any(|sub| sub.get_name() == first);
+    let is_top_level = matches!(first.as_str(), "-h" | "--help" | "-V" | "--version");
+    if !is_subcommand && !is_top_level {
+        args.insert(1, OsString::from("render"));
+    }
+    return args;
+}
+
+// --colors value, "auto" asks the terminal
+pub fn parse_colors(value: &str) -> Result<ColorMode, String> {
+    if value == "auto" {
+        return Ok(crate::detect_color_mode());
+    }
+    return ColorMode::parse(value);
+}
+
+// A positive number of seconds, eg "0.5"
+pub fn parse_seconds(value: &str) -> Result<Duration, String> {
+    let secs: f64 = value.parse().map_err(|_| format!("Invalid number of seconds: {}", value))?;
+    if !secs.is_finite() || secs <= 0.0 {
+        return Err("must be greater than 0".to_string());
+    }
+    return Ok(Duration::from_secs_f64(secs));
+}
+
+// Parse two numbers separated by sep, eg "8x16"
+fn parse_pair<T: std::str::FromStr>(value: &str, sep: char) -> Result<(T, T), String> {
+    let invalid = || format!("expected two numbers separated by '{}': {}", sep, value);
+    let (a, b) = value.split_once(sep).ok_or_else(invalid)?;
+    return Ok((a.trim().parse().map_err(|_| invalid())?, b.trim().parse().map_err(|_| invalid())?));
+}
+
+// --tile-offset value, eg "3,-2"
+pub fn parse_offset(value: &str) -> Result<(i32, i32), String> {
+    return parse_pair(value, ',');
+}
+
+// --cell-size value, eg "8x16"
+pub fn parse_cell_size(value: &str) -> Result<(i32, i32), String> {
+    let (w, h) = parse_pair(value, 'x')?;
+    if w < 1 || h < 1 {
+        return Err("cell size must be at least 1x1".to_string());
+    }
+    return Ok((w, h));
+}
+
+// --contain-within value, the top left cell then the size, eg "40,0,40x20"
+pub fn parse_rect(value: &str) -> Result<(i32, i32, i32, i32), String> {
+    let invalid = || format!("expected X,Y,WxH: {}", value);
+    let (origin, size) = value.rsplit_once(',').ok_or_else(invalid)?;
+    let (x, y) = parse_pair::<i32>(origin, ',').map_err(|_| invalid())?;
+    let (w, h) = parse_pair::<i32>(size, 'x').map_err(|_| invalid())?;
+    if x < 0 || y < 0 || w < 1 || h < 1 {
+        return Err("the rectangle must start on screen and be at least 1x1".to_string());
+    }
+    return Ok((x, y, w, h));
+}
+
+// --luma-weights value, eg "0.2126,0.7152,0.0722", scaled to add up to 1
+pub fn parse_weights(value: &str) -> Result<(f32, f32, f32), String> {
+    let invalid = || format!("expected three weights separated by ',': {}", value);
+    let weights = value.split(',').map(|w| w.trim().parse::<f32>().map_err(|_| invalid())).collect::<Result<Vec<_>, _>>()?;
+    if weights.len() != 3 {
+        return Err(invalid());
+    }
+    if weights.iter().any(|w| !w.is_finite() || *w < 0.0) {
+        return Err("weights can't be negative".to_string());
+    }
+    let total: f32 = weights.iter().sum();
+    if total <= 0.0 {
+        return Err("at least one weight has to be above 0".to_string());
+    }
+    return Ok((weights[0] / total, weights[1] / total, weights[2] / total));
+}
+
+// Two RGB colors, eg the squares of the checkerboard
+pub type ColorPair = ((u8, u8, u8), (u8, u8, u8));
+
+// --checker-colors value, two hex colors eg "#cccccc,#999999"
+pub fn parse_color_pair(value: &str) -> Result<ColorPair, String> {
+    let (a, b) = value.split_once(',').ok_or_else(|| format!("expected two hex colors separated by ',': {}", value))?;
+    return Ok((palette::parse_hex(a.trim())?, palette::parse_hex(b.trim())?));
+}
+
+// One --map pair, "from=to" in hex eg "#ff0000=#00ff00"
+pub fn parse_color_swap(value: &str) -> Result<ColorPair, String> {
+    let (from, to) = value.split_once('=').ok_or_else(|| format!("expected FROM=TO hex colors: {}", value))?;
+    return Ok((palette::parse_hex(from.trim())?, palette::parse_hex(to.trim())?));
+}
+
+// --link value. OSC 8 only carries printable ASCII, anything else in the
+// URL has to be percent-encoded already.
+pub fn parse_link(value: &str) -> Result<String, String> {
+    if value.is_empty() {
+        return Err("the URL is empty".to_string());
+    }
+    if let Some(c) = value.chars().find(|c| !c.is_ascii_graphic()) {
+        return Err(format!("{:?} has to be percent-encoded in the URL", c));
+    }
+    return Ok(value.to_string());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_strs(args: &[&str]) -> Result<Cli, clap::Error> {
+        return parse(args.iter().map(OsString::from));
+    }
+
+    fn render_options(args: &[&str]) -> Box<Options> {
+        match parse_strs(args).unwrap().command {
+            Command::Render(options) => return options,
+            other => panic!("{:?} parsed as {:?}", args, other),
+        }
+    }
+
+    #[test]
+    fn bare_path_means_render() {
+        for args in [&["imprev", "demo.png"][..], &["imprev", "--tile-offset", "1,2", "demo.png"], &["imprev", "render", "demo.png"]] {
+            match parse_strs(args).unwrap().command {
+                Command::Render(options) => assert_eq!(options.image_path, "demo.png"),
+                other => panic!("{:?} parsed as {:?}", args, other),
+            }
+        }
+        match parse_strs(&["imprev", "-n", "3", "palette", "x.png"]) {
+            // Flags before the subcommand belong to render, which has no -n
+            Err(e) => assert_eq!(e.kind(), clap::error::ErrorKind::UnknownArgument),
+            Ok(cli) => panic!("parsed as {:?}", cli.command),
+        }
+    }
+
+    #[test]
+    fn compare_takes_two_paths() {
+        match parse_strs(&["imprev", "compare", "a.png", "b.png"]).unwrap().command {
+            Command::Compare { first, second, split, .. } => {
+                assert_eq!((first.as_str(), second.as_str(), split), ("a.png", "b.png", None));
+            },
+            other => panic!("parsed as {:?}", other),
+        }
+    }
+
+    #[test]
+    fn repeats_imply_tiling() {
+        assert!(render_options(&["imprev", "--repeat-x", "2", "tex.png"]).tile);
+    }
+
+    #[test]
+    fn loop_conflicts_with_play_once() {
+        assert!(parse_strs(&["imprev", "--loop", "--play-once", "a.gif"]).is_err());
+    }
+
+    #[test]
+    fn cell_size_is_at_least_one_pixel() {
+        assert_eq!(parse_cell_size("8x16"), Ok((8, 16)));
+        assert!(parse_strs(&["imprev", "--cell-size", "0x4", "a.png"]).is_err());
+    }
+
+    #[test]
+    fn stills_print_inline_unless_redrawn() {
+        assert!(!render_options(&["imprev", "plot.png"]).fullscreen);
+        let options = render_options(&["imprev", "--refresh", "2", "plot.png"]);
+        assert!(options.fullscreen && options.watching());
+        assert!(parse_strs(&["imprev", "--fullscreen", "--preview-at-cursor", "a.png"]).is_err());
+    }
+
+    #[test]
+    fn interactive_conflicts_with_once() {
+        assert!(parse_strs(&["imprev", "--interactive", "--once", "a.png"]).is_err());
+    }
+
+    #[test]
+    fn watch_dir_stands_in_for_the_path() {
+        let options = render_options(&["imprev", "--watch-dir", "shots"]);
+        assert!(options.fullscreen && options.image_path.is_empty());
+        assert!(parse_strs(&["imprev", "--watch-dir", "shots", "a.png"]).is_err());
+        // Without it the path is required
+        assert!(parse_strs(&["imprev", "render"]).is_err());
+    }
+
+    #[test]
+    fn contain_within_rect() {
+        assert_eq!(parse_rect("40,2,40x20"), Ok((40, 2, 40, 20)));
+        assert!(parse_rect("40,2").is_err());
+        assert!(parse_rect("-1,0,4x4").is_err());
+    }
+
+    #[test]
+    fn max_colors_implies_adaptive_palette() {
+        assert!(render_options(&["imprev", "--max-colors", "32", "a.png"]).adaptive_palette);
+        assert!(parse_strs(&["imprev", "--max-colors", "1", "a.png"]).is_err());
+        assert!(parse_strs(&["imprev", "--max-colors", "257", "a.png"]).is_err());
+    }
+
+    #[test]
+    fn bare_title_leaves_the_path() {
+        let options = render_options(&["imprev", "--title", "a.png"]);
+        assert_eq!((options.title, options.image_path.as_str()), (Some(None), "a.png"));
+    }
+
+    #[test]
+    fn luma_weights_add_up_to_one() {
+        assert_eq!(parse_weights("2,1,1"), Ok((0.5, 0.25, 0.25)));
+        assert!(parse_weights("1,1").is_err());
+        assert!(parse_weights("1,-1,1").is_err());
+        assert!(parse_weights("0,0,0").is_err());
+    }
+
+    #[test]
+    fn compare_split_is_a_percentage() {
+        match parse_strs(&["imprev", "compare", "--split", "30", "a.png", "b.png"]).unwrap().command {
+            Command::Compare { split, .. } => assert_eq!(split, Some(30)),
+            other => panic!("parsed as {:?}", other),
+        }
+        assert!(parse_strs(&["imprev", "compare", "--split", "101", "a.png", "b.png"]).is_err());
+    }
+
+    #[test]
+    fn checker_options_imply_checkerboard() {
+        assert_eq!(parse_color_pair("#fff, 000"), Ok(((255, 255, 255), (0, 0, 0))));
+        assert!(parse_color_pair("#fff").is_err());
+        assert!(render_options(&["imprev", "--checker-size", "4", "logo.png"]).checkerboard);
+        // The background image covers where the squares would go
+        for flag in [&["--checker-size", "4"][..], &["--checker-colors", "#fff,#000"], &["--checkerboard"]] {
+            let args = [&["imprev"][..], flag, &["--background-image", "bg.png", "logo.png"]].concat();
+            assert_eq!(parse_strs(&args).unwrap_err().kind(), clap::error::ErrorKind::ArgumentConflict);
+        }
+    }
+
+    #[test]
+    fn link_urls() {
+        assert_eq!(parse_link("https://example.com/a.png?w=1;h=2"), Ok("https://example.com/a.png?w=1;h=2".to_string()));
+        assert!(parse_link("https://example.com/a b.png").is_err());
+        assert!(parse_link("").is_err());
+    }
+
+    #[test]
+    fn color_swaps() {
+        assert_eq!(parse_color_swap("f00=#00ff00"), Ok(((255, 0, 0), (0, 255, 0))));
+        assert!(parse_color_swap("#f00,#0f0").is_err());
+        // Split at the commas and across flags, in order
+        let options = render_options(&["imprev", "--map", "f00=0f0,00f=fff", "--map", "000=111", "sprite.png"]);
+        assert_eq!(options.map.len(), 3);
+        assert_eq!(options.map[2], ((0, 0, 0), (0x11, 0x11, 0x11)));
+        // Exact matches unless asked, whatever --tolerance says
+        assert_eq!(options.map_tolerance, 0);
+        let options = render_options(&["imprev", "--tolerance", "40", "--map-tolerance", "8", "--map", "f00=0f0", "sprite.png"]);
+        assert_eq!((options.tolerance, options.map_tolerance), (40, 8));
+    }
+
+    #[test]
+    fn hold_plays_once() {
+        let options = render_options(&["imprev", "--hold", "2", "clip.gif"]);
+        assert!(options.play_once);
+        let options = render_options(&["imprev", "--hold", "2", "--play-exit", "clip.gif"]);
+        assert!(options.play_exit && !options.play_once);
+        assert!(parse_strs(&["imprev", "--hold", "2", "--loop", "clip.gif"]).is_err());
+    }
+}
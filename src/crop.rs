@@ -0,0 +1,128 @@
+use opencv::{
+    core,
+    imgproc,
+    prelude::*,
+    Result,
+};
+
+use crate::filters;
+
+// Longest side of the downscaled copy that edge energy is measured on
+const ENERGY_SIZE: i32 = 256;
+
+// Crop the image to a width/height ratio, keeping the region with the most
+// edge energy (a cheap stand-in for saliency) in brightness weighted by
+// `weights`. Falls back to a center crop when the image has no detail to go on.
+pub fn smart_crop(image: &Mat, aspect: f32, weights: (f32, f32, f32)) -> Result<Mat> {
+    let size = image.size()?;
+    let (width, height) = (size.width, size.height);
+    if width == 0 || height == 0 || aspect <= 0.0 {
+        return image.try_clone();
+    }
+
+    // Too wide for the target means we slide a window along the columns
+    let horizontal = width as f32 / height as f32 > aspect;
+    let (crop_width, crop_height) = if horizontal {
+        (((height as f32 * aspect).round() as i32).clamp(1, width), height)
+    } else {
+        (width, ((width as f32 / aspect).round() as i32).clamp(1, height))
+    };
+    let extent = if horizontal { width } else { height };
+    let window = if horizontal { crop_width } else { crop_height };
+
+    let best = match energy_profile(image, horizontal, weights) {
+        Ok(profile) => best_window(&profile, window as f64 / extent as f64),
+        Err(_) => None,
+    };
+    let offset = match best {
+        Some(fraction) => (fraction * extent as f64).round() as i32,
+        None => (extent - window) / 2,
+    }.clamp(0, extent - window);
+
+    let rect = if horizontal {
+        core::Rect::new(offset, 0, crop_width, crop_height)
+    } else {
+        core::Rect::new(0, offset, crop_width, crop_height)
+    };
+    return Mat::roi(image, rect)?.try_clone();
+}
+
+// Gradient magnitude summed across each column (or row) of a small copy
+fn energy_profile(image: &Mat, horizontal: bool, weights: (f32, f32, f32)) -> Result<Vec<f64>> {
+    let size = image.size()?;
+    let scale = ENERGY_SIZE as f64 / size.width.max(size.height) as f64;
+    let small_size = core::Size::new(
+        ((size.width as f64 * scale).round() as i32).max(1),
+        ((size.height as f64 * scale).round() as i32).max(1),
+    );
+    let mut small = Mat::default();
+    imgproc::resize(image, &mut small, small_size, 0.0, 0.0, imgproc::INTER_AREA)?;
+
+    let gray = filters::luminance(&small, weights)?;
+
+    let mut dx = Mat::default();
+    let mut dy = Mat::default();
+    imgproc::sobel(&gray, &mut dx, core::CV_32F, 1, 0, 3, 1.0, 0.0, core::BORDER_DEFAULT)?;
+    imgproc::sobel(&gray, &mut dy, core::CV_32F, 0, 1, 3, 1.0, 0.0, core::BORDER_DEFAULT)?;
+    let mut energy = Mat::default();
+    core::magnitude(&dx, &dy, &mut energy)?;
+
+    let length = if horizontal { energy.cols() } else { energy.rows() };
+    let mut profile = vec![0.0; length as usize];
+    for r in 0..energy.rows() {
+        for c in 0..energy.cols() {
+            let index = if horizontal { c } else { r };
+            profile[index as usize] += *energy.at_2d::<f32>(r, c)? as f64;
+        }
+    }
+    return Ok(profile);
+}
+
+// Start of the best window covering `fraction` of the profile, as a fraction
+// of its length. Windows nearer the middle get a boost so that ties and
+// similar scores favor a centered crop. None if there's no energy at all.
+fn best_window(profile: &[f64], fraction: f64) -> Option<f64> {
+    let n = profile.len();
+    let total: f64 = profile.iter().sum();
+    if n == 0 || total <= f64::EPSILON {
+        return None;
+    }
+    let window = ((fraction * n as f64).round() as usize).clamp(1, n);
+
+    let mut sum: f64 = profile[..window].iter().sum();
+    let mut best = (f64::MIN, 0);
+    for start in 0..=(n - window) {
+        if start > 0 {
+            sum += profile[start + window - 1] - profile[start - 1];
+        }
+        let center = start as f64 + window as f64 / 2.0;
+        let drift = (center - n as f64 / 2.0).abs() / (n as f64 / 2.0);
+        let score = sum * (1.0 - 0.25 * drift);
+        if score > best.0 {
+            best = (score, start);
+        }
+    }
+    return Some(best.1 as f64 / n as f64);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn window_follows_the_detail() {
+        let mut profile = vec![0.0; 100];
+        for value in &mut profile[70..90] {
+            *value = 10.0;
+        }
+        let start = best_window(&profile, 0.2).unwrap();
+        assert!((start - 0.7).abs() < 0.011, "start was {}", start);
+    }
+
+    #[test]
+    fn flat_profile_has_no_preference() {
+        assert_eq!(best_window(&[0.0; 10], 0.5), None);
+        // Uniform detail lands in the middle
+        assert_eq!(best_window(&[1.0; 10], 0.5), Some(0.2));
+    }
+}
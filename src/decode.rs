@@ -0,0 +1,148 @@
+// Test helper: play the escape sequences a frame is made of back onto a
+// grid of background colors, so emitters can be compared by what ends up
+// on screen instead of byte for byte. Understands what the emitters write:
+// SGR colors and resets, printed characters, newlines, carriage returns,
+// erase to end of line, cursor up, forward and position, and saving and
+// restoring the cursor. Cells nothing was printed in stay
+// Color::Transparent.
+
+use crate::palette::Color;
+
+pub fn decode(text: &str) -> Vec<Vec<Color>> {
+    return play(text).into_iter().map(|row| row.into_iter().map(|(color, _)| color).collect()).collect();
+}
+
+// The characters that end up on screen instead, a space where nothing was
+// printed
+pub fn decode_glyphs(text: &str) -> Vec<String> {
+    return play(text).into_iter().map(|row| row.into_iter().map(|(_, glyph)| glyph).collect()).collect();
+}
+
+fn play(text: &str) -> Vec<Vec<(Color, char)>> {
+    let blank = (Color::Transparent, ' ');
+    let mut grid: Vec<Vec<(Color, char)>> = Vec::new();
+    let (mut row, mut col): (usize, usize) = (0, 0);
+    let mut saved = (0, 0);
+    let mut background = Color::Transparent;
+    let mut chars = text.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\x1B' => {
+                match chars.next() {
+                    Some('[') => {},
+                    Some('7') => {
+                        saved = (row, col);
+                        continue;
+                    },
+                    Some('8') => {
+                        (row, col) = saved;
+                        continue;
+                    },
+                    other => panic!("unexpected escape sequence starting with {:?}", other),
+                }
+                let mut params = String::new();
+                let command = loop {
+                    match chars.next() {
+                        Some(c) if c.is_ascii_digit() || c == ';' => params.push(c),
+                        Some(c) => break c,
+                        None => panic!("unterminated escape sequence"),
+                    }
+                };
+                let numbers: Vec<u32> = params.split(';').map(|n| n.parse().unwrap_or(0)).collect();
+                match command {
+                    'm' => background = sgr_background(&numbers, background),
+                    'A' => row = row.saturating_sub(numbers[0].max(1) as usize),
+                    'C' => col += numbers[0].max(1) as usize,
+                    'H' => {
+                        row = numbers[0].max(1) as usize - 1;
+                        col = numbers.get(1).map_or(1, |&c| c.max(1)) as usize - 1;
+                    },
+                    // Erasing only matters for what an older frame left behind
+                    'K' => {},
+                    other => panic!("unexpected escape sequence ending in {:?}", other),
+                }
+            },
+            '\n' => {
+                row += 1;
+                col = 0;
+            },
+            '\r' => col = 0,
+            _ => {
+                if grid.len() <= row {
+                    grid.resize(row + 1, Vec::new());
+                }
+                if grid[row].len() <= col {
+                    grid[row].resize(col + 1, blank);
+                }
+                grid[row][col] = (background, ch);
+                col += 1;
+            },
+        }
+    }
+    // Same width everywhere, like a colormap
+    let width = grid.iter().map(|row| row.len()).max().unwrap_or(0);
+    for row in grid.iter_mut() {
+        row.resize(width, blank);
+    }
+    return grid;
+}
+
+// The background after an SGR sequence, foreground colors are skipped over
+fn sgr_background(numbers: &[u32], mut background: Color) -> Color {
+    let mut i = 0;
+    while i < numbers.len() {
+        match numbers[i] {
+            0 | 49 => background = Color::Transparent,
+            n @ 40..=47 => background = Color::Indexed((n - 40) as u8),
+            n @ 100..=107 => background = Color::Indexed((n - 100 + 8) as u8),
+            48 if numbers.get(i + 1) == Some(&5) => {
+                background = Color::Indexed(numbers[i + 2] as u8);
+                i += 2;
+            },
+            48 if numbers.get(i + 1) == Some(&2) => {
+                background = Color::Rgb(numbers[i + 2] as u8, numbers[i + 3] as u8, numbers[i + 4] as u8);
+                i += 4;
+            },
+            38 if numbers.get(i + 1) == Some(&5) => i += 2,
+            38 if numbers.get(i + 1) == Some(&2) => i += 4,
+            _ => {},
+        }
+        i += 1;
+    }
+    return background;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{write_bitmap, Placement};
+
+    #[test]
+    fn every_placement_draws_the_same_grid() {
+        let (red, blue) = (Color::Indexed(196), Color::Rgb(0, 0, 255));
+        let colormap = vec![
+            vec![red, Color::Transparent, Color::Transparent, blue],
+            vec![Color::Indexed(3), Color::Indexed(12), red, Color::Transparent],
+        ];
+        let mut drawn = Vec::new();
+        for placement in [Placement::Flow, Placement::Plain, Placement::At(0, 0)] {
+            let mut out = String::new();
+            write_bitmap(&mut out, &colormap, (4, 2), placement);
+            drawn.push(decode(&out));
+        }
+        // Plain prints spaces where the others jump ahead, both leave the
+        // terminal's own background
+        for grid in drawn {
+            assert_eq!(grid, colormap);
+        }
+    }
+
+    #[test]
+    fn placed_frames_land_at_their_origin() {
+        let red = Color::Indexed(196);
+        let mut out = String::new();
+        write_bitmap(&mut out, &[vec![red]], (1, 1), Placement::At(2, 1));
+        let blank = Color::Transparent;
+        assert_eq!(decode(&out), vec![vec![blank; 3], vec![blank, blank, red]]);
+    }
+}
@@ -0,0 +1,204 @@
+use crate::Placement;
+use crate::palette::Color;
+
+// How many pixels of the resized image share one terminal cell
+#[derive(Clone, Copy, Debug, Default, PartialEq, clap::ValueEnum)]
+pub enum Density {
+    #[default]
+    #[value(name = "1")]
+    One,    // A space with a background color
+    #[value(name = "2")]
+    Two,    // Half blocks, one pixel above the other
+    #[value(name = "4")]
+    Four,   // Quadrant blocks, 2x2
+    #[value(name = "8")]
+    Eight,  // Braille dots, 2x4
+}
+
+impl Density {
+    // Pixels across and down in each cell
+    pub fn cell_pixels(self) -> (i32, i32) {
+        match self {
+            Density::One => (1, 1),
+            Density::Two => (1, 2),
+            Density::Four => (2, 2),
+            Density::Eight => (2, 4),
+        }
+    }
+
+    // The character showing the pixels whose bits are set in the foreground
+    // color. Bits go across then down, bit 0 is the top left pixel.
+    fn glyph(self, mask: u32) -> char {
+        match self {
+            Density::One => if mask == 0 { ' ' } else { '█' },
+            Density::Two => HALF_BLOCKS[mask as usize],
+            Density::Four => QUADRANTS[mask as usize],
+            Density::Eight => {
+                let dots = (0..8).filter(|i| mask & (1 << i) != 0).fold(0, |dots, i| dots | BRAILLE_DOTS[i]);
+                char::from_u32(0x2800 + dots).unwrap_or(' ')
+            },
+        }
+    }
+}
+
+const HALF_BLOCKS: [char; 4] = [' ', '▀', '▄', '█'];
+
+const QUADRANTS: [char; 16] = [
+    ' ', '▘', '▝', '▀', '▖', '▌', '▞', '▛',
+    '▗', '▚', '▐', '▜', '▄', '▙', '▟', '█',
+];
+
+// Braille dot bit for each pixel of the 2x4 cell, across then down
+const BRAILLE_DOTS: [u32; 8] = [0x01, 0x08, 0x02, 0x10, 0x04, 0x20, 0x40, 0x80];
+
+// A character drawn in one color over another
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cell {
+    pub glyph: char,
+    pub fg: Color,
+    pub bg: Color,
+}
+
+// Group the pixels of a colormap into cells. A cell can only show two
+// colors, so its pixels are split between the two that are furthest apart.
+// None for cells with nothing but transparent pixels.
+pub fn group(colormap: &[Vec<Color>], density: Density) -> Vec<Vec<Option<Cell>>> {
+    let (across, down) = density.cell_pixels();
+    let (across, down) = (across as usize, down as usize);
+    let height = colormap.len();
+    let width = colormap.first().map_or(0, |row| row.len());
+    let pixel = |r: usize, c: usize| colormap.get(r).and_then(|row| row.get(c)).copied().unwrap_or(Color::Transparent);
+
+    let mut cells = Vec::with_capacity(height.div_ceil(down));
+    for top in (0..height).step_by(down) {
+        let mut row = Vec::with_capacity(width.div_ceil(across));
+        for left in (0..width).step_by(across) {
+            let pixels: Vec<Color> = (0..down)
+                .flat_map(|y| (0..across).map(move |x| (y, x)))
+                .map(|(y, x)| pixel(top + y, left + x))
+                .collect();
+            row.push(split(&pixels).map(|(mask, fg, bg)| {
+                let full = (1 << pixels.len()) - 1;
+                // Solid cells are drawn as a background, it's what density 1 does
+                match mask {
+                    0 => Cell { glyph: ' ', fg: bg, bg },
+                    _ if mask == full && bg != Color::Transparent => Cell { glyph: ' ', fg, bg: fg },
+                    _ => Cell { glyph: density.glyph(mask), fg, bg },
+                }
+            }));
+        }
+        cells.push(row);
+    }
+    return cells;
+}
+
+// Pick the two colors of a cell and which pixels get the first one
+fn split(pixels: &[Color]) -> Option<(u32, Color, Color)> {
+    let opaque: Vec<Color> = pixels.iter().copied().filter(|&p| p != Color::Transparent).collect();
+    if opaque.is_empty() {
+        return None;
+    }
+    let distance = |a: Color, b: Color| {
+        let (a, b) = (a.to_rgb(), b.to_rgb());
+        let d = |x: u8, y: u8| (x as i32 - y as i32).pow(2);
+        d(a.0, b.0) + d(a.1, b.1) + d(a.2, b.2)
+    };
+
+    let (fg, bg) = if opaque.len() < pixels.len() {
+        // The terminal shows through the transparent pixels, the rest get
+        // their most common color
+        let fg = *opaque.iter().max_by_key(|&&p| opaque.iter().filter(|&&q| q == p).count()).unwrap();
+        (fg, Color::Transparent)
+    } else {
+        let mut farthest = (0, opaque[0], opaque[0]);
+        for (i, &a) in opaque.iter().enumerate() {
+            for &b in &opaque[i + 1..] {
+                if distance(a, b) > farthest.0 {
+                    farthest = (distance(a, b), a, b);
+                }
+            }
+        }
+        (farthest.1, farthest.2)
+    };
+
+    let mut mask = 0;
+    for (i, &p) in pixels.iter().enumerate() {
+        let foreground = match bg {
+            Color::Transparent => p != Color::Transparent,
+            _ => distance(p, fg) <= distance(p, bg),
+        };
+        if foreground {
+            mask |= 1 << i;
+        }
+    }
+    return Some((mask, fg, bg));
+}
+
+// Append the escape sequences that draw the cells, one line per row
+pub fn write_cells(out: &mut String, cells: &[Vec<Option<Cell>>], placement: Placement) {
+    for (r, row) in cells.iter().enumerate() {
+        placement.start_row(out, r);
+        let mut skipped = 0;  // Empty cells to jump over before the next paint
+        for cell in row {
+            let cell = match cell {
+                Some(cell) => cell,
+                None => {
+                    skipped += 1;
+                    continue;
+                }
+            };
+            if skipped > 0 {
+                placement.skip(out, skipped);
+                skipped = 0;
+            }
+            out.push_str(&format!("{}{}{}\x1B[0m", cell.fg.foreground(), cell.bg.background(), cell.glyph));
+        }
+        placement.end_row(out);
+    }
+    out.push_str("\x1B[0m");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode::{decode, decode_glyphs};
+
+    const RED: Color = Color::Indexed(196);
+    const BLUE: Color = Color::Indexed(21);
+    const CLEAR: Color = Color::Transparent;
+
+    #[test]
+    fn half_blocks() {
+        let colormap = vec![vec![RED, RED, CLEAR], vec![BLUE, RED, CLEAR], vec![CLEAR, RED, RED]];
+        let cells = group(&colormap, Density::Two);
+        assert_eq!(cells.len(), 2);
+        assert_eq!(cells[0][0], Some(Cell { glyph: '▀', fg: RED, bg: BLUE }));
+        assert_eq!(cells[0][1], Some(Cell { glyph: ' ', fg: RED, bg: RED }));
+        assert_eq!(cells[0][2], None);
+        // The missing row below counts as transparent
+        assert_eq!(cells[1][0], None);
+        assert_eq!(cells[1][2], Some(Cell { glyph: '▀', fg: RED, bg: CLEAR }));
+    }
+
+    #[test]
+    fn quadrants_and_braille() {
+        let colormap = vec![vec![RED, BLUE], vec![BLUE, RED]];
+        assert_eq!(group(&colormap, Density::Four)[0][0], Some(Cell { glyph: '▚', fg: RED, bg: BLUE }));
+
+        let colormap = vec![vec![RED, CLEAR], vec![CLEAR, CLEAR], vec![CLEAR, CLEAR], vec![CLEAR, RED]];
+        // Dots 1 and 8
+        assert_eq!(group(&colormap, Density::Eight)[0][0], Some(Cell { glyph: '\u{2881}', fg: RED, bg: CLEAR }));
+    }
+
+    #[test]
+    fn cells_round_trip() {
+        let colormap = vec![vec![RED, RED, CLEAR], vec![BLUE, RED, CLEAR], vec![CLEAR, RED, RED]];
+        let cells = group(&colormap, Density::Two);
+        for placement in [Placement::Flow, Placement::Plain, Placement::At(0, 0)] {
+            let mut out = String::new();
+            write_cells(&mut out, &cells, placement);
+            assert_eq!(decode_glyphs(&out), vec!["▀  ", " ▀▀"]);
+            assert_eq!(decode(&out), vec![vec![BLUE, RED, CLEAR], vec![CLEAR; 3]]);
+        }
+    }
+}
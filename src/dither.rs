@@ -0,0 +1,125 @@
+use crate::palette::Color;
+
+// How quantization error is hidden when snapping cells to a palette
+#[derive(Clone, Copy, Debug, Default, PartialEq, clap::ValueEnum)]
+pub enum Dither {
+    #[default]
+    None,   // Nearest color, flat areas band
+    Floyd,  // Floyd-Steinberg error diffusion, smoothest, but the pattern crawls between frames
+    Bayer,  // Ordered 8x8 threshold, the same pattern every frame
+}
+
+// Thresholds 0..64, each cell of the pattern nudges its pixel by a different amount
+const BAYER_8: [[u8; 8]; 8] = [
+    [0, 32, 8, 40, 2, 34, 10, 42],
+    [48, 16, 56, 24, 50, 18, 58, 26],
+    [12, 44, 4, 36, 14, 46, 6, 38],
+    [60, 28, 52, 20, 62, 30, 54, 22],
+    [3, 35, 11, 43, 1, 33, 9, 41],
+    [51, 19, 59, 27, 49, 17, 57, 25],
+    [15, 47, 7, 39, 13, 45, 5, 37],
+    [63, 31, 55, 23, 61, 29, 53, 21],
+];
+
+// How far the Bayer threshold pushes a channel either way, about one step
+// of the 256 color cube
+const BAYER_SPREAD: f32 = 48.0;
+
+// Amount added to each channel of the cell at row r, column c, centered on 0
+pub fn bayer_offset(r: i32, c: i32) -> f32 {
+    let threshold = BAYER_8[r.rem_euclid(8) as usize][c.rem_euclid(8) as usize] as f32;
+    return ((threshold + 0.5) / 64.0 - 0.5) * BAYER_SPREAD;
+}
+
+// Dithers one colormap, cells have to come in row by row, left to right
+pub struct Ditherer {
+    kind: Dither,
+    row: i32,
+    current: Vec<[f32; 3]>,  // Error carried into this row
+    next: Vec<[f32; 3]>,     // and the one below
+}
+
+impl Ditherer {
+    pub fn new(kind: Dither, width: usize) -> Ditherer {
+        let width = if kind == Dither::Floyd { width } else { 0 };
+        return Ditherer { kind, row: 0, current: vec![[0.0; 3]; width], next: vec![[0.0; 3]; width] };
+    }
+
+    // Pick the color for the cell at row r, column c with `quantize`
+    pub fn pick<F: Fn(u8, u8, u8) -> Color>(&mut self, r: i32, c: i32, (red, green, blue): (u8, u8, u8), quantize: F) -> Color {
+        let wanted = [red as f32, green as f32, blue as f32];
+        match self.kind {
+            Dither::None => return quantize(red, green, blue),
+            Dither::Bayer => {
+                let offset = bayer_offset(r, c);
+                let [red, green, blue] = wanted.map(|v| channel(v + offset));
+                return quantize(red, green, blue);
+            },
+            Dither::Floyd => {},
+        }
+        while self.row < r {
+            self.current = std::mem::replace(&mut self.next, vec![[0.0; 3]; self.current.len()]);
+            self.row += 1;
+        }
+        let c = c as usize;
+        let carried = self.current[c];
+        let wanted = [0, 1, 2].map(|i| (wanted[i] + carried[i]).clamp(0.0, 255.0));
+        let [red, green, blue] = wanted.map(channel);
+        let color = quantize(red, green, blue);
+        let (r, g, b) = color.to_rgb();
+        let error = [wanted[0] - r as f32, wanted[1] - g as f32, wanted[2] - b as f32];
+
+        // 7/16 right, then 3/16, 5/16 and 1/16 along the row below
+        let width = self.current.len();
+        let spread = |row: &mut Vec<[f32; 3]>, c: usize, weight: f32| {
+            for i in 0..3 {
+                row[c][i] += error[i] * weight / 16.0;
+            }
+        };
+        if c + 1 < width {
+            spread(&mut self.current, c + 1, 7.0);
+            spread(&mut self.next, c + 1, 1.0);
+        }
+        if c > 0 {
+            spread(&mut self.next, c - 1, 3.0);
+        }
+        spread(&mut self.next, c, 5.0);
+        return color;
+    }
+}
+
+fn channel(value: f32) -> u8 {
+    return value.round().clamp(0.0, 255.0) as u8;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Two levels about a cube step apart, 0 and 48
+    fn step(r: u8, g: u8, b: u8) -> Color {
+        let level = if (r as u16 + g as u16 + b as u16) / 3 >= 24 { 48 } else { 0 };
+        return Color::Rgb(level, level, level);
+    }
+
+    #[test]
+    fn dithering_keeps_the_average() {
+        for kind in [Dither::Floyd, Dither::Bayer] {
+            let mut ditherer = Ditherer::new(kind, 8);
+            let mut high = 0;
+            for r in 0..8 {
+                for c in 0..8 {
+                    if ditherer.pick(r, c, (20, 20, 20), step) == Color::Rgb(48, 48, 48) {
+                        high += 1;
+                    }
+                }
+            }
+            // 20 is about 40% of the way up
+            assert!((22..=32).contains(&high), "{:?} raised {} of 64 cells", kind, high);
+        }
+        let mut flat = Ditherer::new(Dither::None, 8);
+        assert_eq!(flat.pick(3, 3, (20, 20, 20), step), Color::Rgb(0, 0, 0));
+        // The ordered pattern only depends on the position
+        assert_eq!(bayer_offset(1, 2), bayer_offset(9, 10));
+    }
+}
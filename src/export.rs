@@ -0,0 +1,156 @@
+use opencv::{
+    core,
+    imgcodecs,
+    imgproc,
+    prelude::*,
+    Result,
+};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::time::Instant;
+
+use crate::palette::Color;
+
+// Roughly the proportions of a terminal cell, matching DEFAULT_HEIGHT_RESCALE
+pub const DEFAULT_CELL_SIZE: (i32, i32) = (8, 16);
+
+// How big each pixel of the colormap is drawn when a cell_size cell shows
+// cell_pixels of them, so every density comes out at the terminal's size
+pub fn pixel_size(cell_size: (i32, i32), (across, down): (i32, i32)) -> (i32, i32) {
+    return ((cell_size.0 / across).max(1), (cell_size.1 / down).max(1));
+}
+
+// Draw every pixel of the colormap as a filled rectangle, cell_pixels of
+// them to each cell_size cell
+pub fn rasterize(colormap: &[Vec<Color>], cell_size: (i32, i32), cell_pixels: (i32, i32)) -> Result<Mat> {
+    let cell_size = pixel_size(cell_size, cell_pixels);
+    let rows = colormap.len() as i32;
+    let cols = colormap.first().map_or(0, |row| row.len()) as i32;
+    let mut canvas = Mat::new_rows_cols_with_default(
+        rows * cell_size.1,
+        cols * cell_size.0,
+        core::CV_8UC3,
+        core::Scalar::all(0.0),
+    )?;
+
+    for (r, row) in colormap.iter().enumerate() {
+        for (c, color) in row.iter().enumerate() {
+            let (red, green, blue) = color.to_rgb();
+            let cell = core::Rect::new(c as i32 * cell_size.0, r as i32 * cell_size.1, cell_size.0, cell_size.1);
+            imgproc::rectangle(
+                &mut canvas,
+                cell,
+                core::Scalar::new(blue as f64, green as f64, red as f64, 0.0),  // BGR
+                imgproc::FILLED,
+                imgproc::LINE_8,
+                0,
+            )?;
+        }
+    }
+    return Ok(canvas);
+}
+
+// Write exactly what the terminal shows to an image file
+pub fn save_png(colormap: &[Vec<Color>], path: &str, cell_size: (i32, i32), cell_pixels: (i32, i32)) -> Result<()> {
+    let canvas = rasterize(colormap, cell_size, cell_pixels)?;
+    if !imgcodecs::imwrite(path, &canvas, &core::Vector::new())? {
+        return Err(opencv::Error::new(core::StsError, format!("Could not write image: {}", path)));
+    }
+    return Ok(());
+}
+
+// --record stops adding frames after this many, a long session would
+// otherwise grow the file without bound
+pub const RECORD_FRAME_LIMIT: usize = 1000;
+
+// Shortest frame delay in hundredths of a second, browsers slow anything
+// faster down to 10
+const MIN_GIF_DELAY: u16 = 2;
+
+// Collects rendered frames into an animated GIF, each shown for as long as
+// it stayed up in the terminal
+pub struct Recorder {
+    path: String,
+    file: Option<BufWriter<File>>,  // Until the first frame says how big the GIF is
+    encoder: Option<gif::Encoder<BufWriter<File>>>,
+    size: (i32, i32),
+    pending: Option<(gif::Frame<'static>, Instant)>,  // Written once the next frame replaces it
+    pub frames: usize,
+    pub dropped: usize,  // Frames past the limit
+}
+
+impl Recorder {
+    pub fn create(path: &str) -> std::io::Result<Recorder> {
+        let file = BufWriter::new(File::create(path)?);
+        return Ok(Recorder { path: path.to_string(), file: Some(file), encoder: None, size: (0, 0), pending: None, frames: 0, dropped: 0 });
+    }
+
+    // Add what the terminal shows now. Frames past RECORD_FRAME_LIMIT are
+    // dropped, the return value says whether this one was kept.
+    pub fn add(&mut self, colormap: &[Vec<Color>], cell_size: (i32, i32), cell_pixels: (i32, i32)) -> Result<bool, Box<dyn std::error::Error>> {
+        if self.frames >= RECORD_FRAME_LIMIT {
+            self.dropped += 1;
+            return Ok(false);
+        }
+        let mut canvas = rasterize(colormap, cell_size, cell_pixels)?;
+        if canvas.empty() {
+            return Ok(false);
+        }
+        if let Some(file) = self.file.take() {
+            // GIF sides are 16 bit
+            self.size = (canvas.cols().min(u16::MAX as i32), canvas.rows().min(u16::MAX as i32));
+            let mut encoder = gif::Encoder::new(file, self.size.0 as u16, self.size.1 as u16, &[])?;
+            encoder.set_repeat(gif::Repeat::Infinite)?;
+            self.encoder = Some(encoder);
+        }
+        // Every frame is drawn on the first one's canvas, even after a resize
+        if (canvas.cols(), canvas.rows()) != self.size {
+            let mut resized = Mat::default();
+            imgproc::resize(&canvas, &mut resized, core::Size::new(self.size.0, self.size.1), 0.0, 0.0, imgproc::INTER_NEAREST)?;
+            canvas = resized;
+        }
+        let mut rgb = Mat::default();
+        imgproc::cvt_color(&canvas, &mut rgb, imgproc::COLOR_BGR2RGB, 0)?;
+        let frame = gif::Frame::from_rgb_speed(self.size.0 as u16, self.size.1 as u16, rgb.data_bytes()?, 10);
+
+        self.write_pending()?;
+        self.pending = Some((frame, Instant::now()));
+        self.frames += 1;
+        return Ok(true);
+    }
+
+    fn write_pending(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        if let (Some((mut frame, shown)), Some(encoder)) = (self.pending.take(), self.encoder.as_mut()) {
+            frame.delay = ((shown.elapsed().as_millis() / 10).min(u16::MAX as u128) as u16).max(MIN_GIF_DELAY);
+            encoder.write_frame(&frame)?;
+        }
+        return Ok(());
+    }
+
+    // Write the last frame and close the file. Nothing recorded, no file.
+    pub fn finish(mut self) -> Result<(), Box<dyn std::error::Error>> {
+        self.write_pending()?;
+        match self.encoder.take() {
+            Some(encoder) => encoder.into_inner()?.flush()?,
+            None => {
+                drop(self.file.take());
+                std::fs::remove_file(&self.path)?;
+            },
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pixels_fill_their_cell() {
+        assert_eq!(pixel_size(DEFAULT_CELL_SIZE, (1, 1)), (8, 16));
+        assert_eq!(pixel_size(DEFAULT_CELL_SIZE, (1, 2)), (8, 8));
+        assert_eq!(pixel_size(DEFAULT_CELL_SIZE, (2, 4)), (4, 4));
+        // Never thinner than a pixel
+        assert_eq!(pixel_size((1, 2), (2, 4)), (1, 1));
+    }
+}
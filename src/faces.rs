@@ -0,0 +1,55 @@
+use opencv::{
+    core,
+    imgproc,
+    objdetect::CascadeClassifier,
+    prelude::*,
+    Result,
+};
+use std::path::{Path, PathBuf};
+
+// The frontal face cascade OpenCV ships, and where packages put it
+pub const DEFAULT_CASCADE: &str = "haarcascade_frontalface_default.xml";
+const CASCADE_DIRS: [&str; 5] = [
+    "/usr/share/opencv4/haarcascades",
+    "/usr/share/opencv/haarcascades",
+    "/usr/local/share/opencv4/haarcascades",
+    "/usr/local/share/opencv/haarcascades",
+    "/opt/homebrew/share/opencv4/haarcascades",
+];
+
+// Longest side of the copy that gets searched, faces big enough to see in
+// a terminal are still dozens of pixels across at this size
+const DETECT_SIZE: i32 = 640;
+
+// The installed frontal face cascade, if OpenCV's data files are around
+pub fn default_cascade() -> Option<PathBuf> {
+    return CASCADE_DIRS.iter().map(|dir| Path::new(dir).join(DEFAULT_CASCADE)).find(|path| path.is_file());
+}
+
+pub fn load(path: &str) -> Result<CascadeClassifier, String> {
+    let classifier = CascadeClassifier::new(path).map_err(|e| format!("Could not load the cascade {}: {}", path, e))?;
+    // A file that isn't a cascade loads as an empty one
+    if classifier.empty().unwrap_or(true) {
+        return Err(format!("{} isn't a cascade classifier", path));
+    }
+    return Ok(classifier);
+}
+
+// Bounding boxes of what the classifier finds in an 8 bit BGR(A) image, in
+// pixels of the image
+pub fn detect(classifier: &mut CascadeClassifier, image: &Mat) -> Result<Vec<core::Rect>> {
+    let mut gray = Mat::default();
+    let code = if image.channels() == 4 { imgproc::COLOR_BGRA2GRAY } else { imgproc::COLOR_BGR2GRAY };
+    imgproc::cvt_color(image, &mut gray, code, 0)?;
+    let scale = (DETECT_SIZE as f64 / image.cols().max(image.rows()) as f64).min(1.0);
+    let mut small = Mat::default();
+    imgproc::resize(&gray, &mut small, core::Size::new(0, 0), scale, scale, imgproc::INTER_AREA)?;
+    // Evens out the lighting, the cascades are trained on equalized faces
+    let mut equalized = Mat::default();
+    imgproc::equalize_hist(&small, &mut equalized)?;
+
+    let mut found = core::Vector::<core::Rect>::new();
+    classifier.detect_multi_scale(&equalized, &mut found, 1.1, 3, 0, core::Size::new(0, 0), core::Size::new(0, 0))?;
+    let back = |v: i32| (v as f64 / scale).round() as i32;
+    return Ok(found.iter().map(|r| core::Rect::new(back(r.x), back(r.y), back(r.width), back(r.height))).collect());
+}
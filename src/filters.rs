@@ -0,0 +1,213 @@
+use opencv::{
+    core,
+    imgproc,
+    photo,
+    prelude::*,
+    Result,
+};
+
+use crate::cli::ColorPair;
+
+// Rec. 601 luma weights for red, green and blue, the same as OpenCV's BGR2GRAY
+pub const REC_601: (f32, f32, f32) = (0.299, 0.587, 0.114);
+
+// Brightness of each pixel of an 8 bit BGR(A) image as one 8 bit channel,
+// weighting red, green and blue. Alpha doesn't count.
+pub fn luminance(image: &Mat, (r, g, b): (f32, f32, f32)) -> Result<Mat> {
+    let mut weights = vec![b, g, r];
+    if image.channels() == 4 {
+        weights.push(0.0);
+    }
+    let matrix = Mat::from_slice_2d(&[weights])?;
+    let mut gray = Mat::default();
+    core::transform(image, &mut gray, &matrix)?;
+    return Ok(gray);
+}
+
+// Where an 8 bit BGR(A) image is in focus, for --focus-peaking: 255 on the
+// pixels whose Laplacian (how sharply the brightness, weighted by `weights`,
+// changes around them) is over `threshold`, 0 elsewhere.
+pub fn focus_mask(image: &Mat, threshold: u8, weights: (f32, f32, f32)) -> Result<Mat> {
+    let gray = luminance(image, weights)?;
+    let mut laplacian = Mat::default();
+    imgproc::laplacian(&gray, &mut laplacian, core::CV_16S, 3, 1.0, 0.0, core::BORDER_DEFAULT)?;
+    let mut magnitude = Mat::default();
+    core::convert_scale_abs(&laplacian, &mut magnitude, 1.0, 0.0)?;
+    let mut mask = Mat::default();
+    imgproc::threshold(&magnitude, &mut mask, threshold as f64, 255.0, imgproc::THRESH_BINARY)?;
+    return Ok(mask);
+}
+
+// Mosaic the image into `block` by `block` pixel squares of their average
+// color, for --pixelate. Keeps the size (and an alpha channel), so it goes
+// through the usual resize afterwards like any other image.
+pub fn pixelate(image: &Mat, block: u32) -> Result<Mat> {
+    let size = image.size()?;
+    let block = block as i32;
+    let blocks = core::Size::new((size.width + block - 1) / block, (size.height + block - 1) / block);
+    let mut small = Mat::default();
+    imgproc::resize(image, &mut small, blocks, 0.0, 0.0, imgproc::INTER_AREA)?;
+    let mut mosaic = Mat::default();
+    imgproc::resize(&small, &mut mosaic, size, 0.0, 0.0, imgproc::INTER_NEAREST)?;
+    return Ok(mosaic);
+}
+
+// Color vision deficiencies we can simulate
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Cvd {
+    Protanopia,    // No red cones
+    Deuteranopia,  // No green cones
+    Tritanopia,    // No blue cones
+}
+
+impl Cvd {
+    pub fn parse(value: &str) -> Result<Cvd, String> {
+        match value {
+            "protanopia" => Ok(Cvd::Protanopia),
+            "deuteranopia" => Ok(Cvd::Deuteranopia),
+            "tritanopia" => Ok(Cvd::Tritanopia),
+            _ => Err(format!("Invalid --cvd: {} (expected protanopia, deuteranopia or tritanopia)", value)),
+        }
+    }
+
+    // Machado, Oliveira & Fernandes (2009) matrices at full severity, RGB in and out
+    fn rgb_matrix(self) -> [[f32; 3]; 3] {
+        match self {
+            Cvd::Protanopia => [
+                [0.152286, 1.052583, -0.204868],
+                [0.114503, 0.786281, 0.099216],
+                [-0.003882, -0.048116, 1.051998],
+            ],
+            Cvd::Deuteranopia => [
+                [0.367322, 0.860646, -0.227968],
+                [0.280085, 0.672501, 0.047413],
+                [-0.011820, 0.042940, 0.968881],
+            ],
+            Cvd::Tritanopia => [
+                [1.255528, -0.076749, -0.178779],
+                [-0.078411, 0.930809, 0.147602],
+                [0.004733, 0.691367, 0.303900],
+            ],
+        }
+    }
+}
+
+// Display gamma for tone mapped HDR images, their values are linear light
+const TONEMAP_GAMMA: f32 = 2.2;
+
+// Bring a float BGR(A) image whose values go past 1.0 (Radiance HDR, EXR)
+// into 8 bits with Reinhard's operator, so highlights keep their detail
+// instead of clipping. None for float images already in 0-1, which scale as is.
+pub fn tone_map(image: &Mat) -> Result<Option<Mat>> {
+    let mut float = Mat::default();
+    image.convert_to(&mut float, core::CV_32F, 1.0, 0.0)?;
+    let has_alpha = float.channels() == 4;
+    let color = if has_alpha {
+        let mut color = Mat::default();
+        imgproc::cvt_color(&float, &mut color, imgproc::COLOR_BGRA2BGR, 0)?;
+        color
+    } else {
+        float.try_clone()?
+    };
+    let brightest = color
+        .data_bytes()?
+        .chunks_exact(4)
+        .map(|bytes| f32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .fold(0.0, f32::max);
+    if brightest <= 1.0 {
+        return Ok(None);
+    }
+
+    let mut mapped = Mat::default();
+    photo::create_tonemap_reinhard(TONEMAP_GAMMA, 0.0, 1.0, 0.0)?.process(&color, &mut mapped)?;
+    let mut tone_mapped = Mat::default();
+    mapped.convert_to(&mut tone_mapped, core::CV_8U, 255.0, 0.0)?;
+    if has_alpha {
+        let (mut alpha, mut alpha_8u, mut bgra) = (Mat::default(), Mat::default(), Mat::default());
+        core::extract_channel(&float, &mut alpha, 3)?;
+        alpha.convert_to(&mut alpha_8u, core::CV_8U, 255.0, 0.0)?;
+        imgproc::cvt_color(&tone_mapped, &mut bgra, imgproc::COLOR_BGR2BGRA, 0)?;
+        core::insert_channel(&alpha_8u, &mut bgra, 3)?;
+        tone_mapped = bgra;
+    }
+    return Ok(Some(tone_mapped));
+}
+
+// Make every pixel within `tolerance` of the key color on each channel fully
+// transparent, eg the white behind a logo. Always returns BGRA.
+pub fn chroma_key(image: &Mat, key: (u8, u8, u8), tolerance: u8) -> Result<Mat> {
+    let mut keyed = if image.channels() == 4 {
+        image.try_clone()?
+    } else {
+        let mut converted = Mat::default();
+        imgproc::cvt_color(image, &mut converted, imgproc::COLOR_BGR2BGRA, 0)?;
+        converted
+    };
+    let (lower, upper) = key_bounds(key, tolerance);
+    let mut mask = Mat::default();
+    core::in_range(&keyed, &core::Scalar::from(lower), &core::Scalar::from(upper), &mut mask)?;
+    keyed.set_to(&core::Scalar::all(0.0), &mask)?;
+    return Ok(keyed);
+}
+
+// Paint every pixel within `tolerance` of each from color on each channel
+// with its to color, one (from, to) pair after another, so a later pair also
+// sees what the earlier ones painted. Alpha is left as it was.
+pub fn swap_colors(image: &Mat, swaps: &[ColorPair], tolerance: u8) -> Result<Mat> {
+    let mut swapped = image.try_clone()?;
+    let mut alpha = Mat::default();
+    if image.channels() == 4 {
+        core::extract_channel(image, &mut alpha, 3)?;
+    }
+    for &(from, (r, g, b)) in swaps {
+        let (lower, upper) = key_bounds(from, tolerance);
+        let mut mask = Mat::default();
+        core::in_range(&swapped, &core::Scalar::from(lower), &core::Scalar::from(upper), &mut mask)?;
+        swapped.set_to(&core::Scalar::new(b as f64, g as f64, r as f64, 255.0), &mask)?;
+    }
+    if image.channels() == 4 {
+        core::insert_channel(&alpha, &mut swapped, 3)?;
+    }
+    return Ok(swapped);
+}
+
+// BGRA range of the pixels that match the key, any alpha
+fn key_bounds((r, g, b): (u8, u8, u8), tolerance: u8) -> ([f64; 4], [f64; 4]) {
+    let bound = |v: u8, by: i32| (v as i32 + by).clamp(0, 255) as f64;
+    let t = tolerance as i32;
+    return ([bound(b, -t), bound(g, -t), bound(r, -t), 0.0], [bound(b, t), bound(g, t), bound(r, t), 255.0]);
+}
+
+// Show the image the way someone with the deficiency would see it. Works on
+// BGR and BGRA, alpha passes through, and the 8 bit output saturates so
+// results are clamped to 0-255.
+pub fn simulate_cvd(image: &Mat, cvd: Cvd) -> Result<Mat> {
+    let rgb = cvd.rgb_matrix();
+    let channels = image.channels() as usize;
+    // Reorder for BGR: row/column i in BGR is 2 - i in RGB
+    let mut matrix = vec![vec![0f32; channels]; channels];
+    for i in 0..3 {
+        for j in 0..3 {
+            matrix[i][j] = rgb[2 - i][2 - j];
+        }
+    }
+    if channels == 4 {
+        matrix[3][3] = 1.0;
+    }
+    let matrix = Mat::from_slice_2d(&matrix)?;
+    let mut simulated = Mat::default();
+    core::transform(image, &mut simulated, &matrix)?;
+    return Ok(simulated);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_bounds_clamp() {
+        assert_eq!(key_bounds((255, 255, 255), 20), ([235.0, 235.0, 235.0, 0.0], [255.0, 255.0, 255.0, 255.0]));
+        assert_eq!(key_bounds((0, 177, 64), 10), ([54.0, 167.0, 0.0, 0.0], [74.0, 187.0, 10.0, 255.0]));
+        assert_eq!(key_bounds((10, 20, 30), 0), ([30.0, 20.0, 10.0, 0.0], [30.0, 20.0, 10.0, 255.0]));
+    }
+}
@@ -0,0 +1,70 @@
+use std::fs::File;
+use std::io::Read;
+
+// How much of the file to look through for the size, JPEG can put a lot of
+// metadata before it
+const HEADER_LIMIT: u64 = 1 << 20;
+
+// Width and height from the header of a PNG or JPEG file, without decoding it
+pub fn read_dimensions(path: &str) -> Option<(i32, i32)> {
+    let mut bytes = Vec::new();
+    File::open(path).ok()?.take(HEADER_LIMIT).read_to_end(&mut bytes).ok()?;
+    return dimensions(&bytes);
+}
+
+pub fn dimensions(bytes: &[u8]) -> Option<(i32, i32)> {
+    if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        // IHDR always comes first
+        if bytes.get(12..16)? != b"IHDR" {
+            return None;
+        }
+        let width = u32::from_be_bytes(bytes.get(16..20)?.try_into().ok()?);
+        let height = u32::from_be_bytes(bytes.get(20..24)?.try_into().ok()?);
+        return Some((width as i32, height as i32));
+    }
+    if bytes.starts_with(&[0xFF, 0xD8]) {
+        return jpeg_dimensions(bytes);
+    }
+    return None;
+}
+
+// The start of frame segment has the size, every other segment is skipped
+fn jpeg_dimensions(bytes: &[u8]) -> Option<(i32, i32)> {
+    let mut offset = 2;
+    while offset + 4 <= bytes.len() && bytes[offset] == 0xFF {
+        let marker = bytes[offset + 1];
+        let length = u16::from_be_bytes([bytes[offset + 2], bytes[offset + 3]]) as usize;
+        // SOF0 to SOF15, minus DHT (C4), JPG (C8) and DAC (CC) which share the range
+        if (0xC0..=0xCF).contains(&marker) && !matches!(marker, 0xC4 | 0xC8 | 0xCC) {
+            let segment = bytes.get(offset + 4..offset + 9)?;
+            let height = u16::from_be_bytes([segment[1], segment[2]]);
+            let width = u16::from_be_bytes([segment[3], segment[4]]);
+            return Some((width as i32, height as i32));
+        }
+        offset += 2 + length;
+    }
+    return None;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes_from_headers() {
+        let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+        png.extend(6000u32.to_be_bytes());
+        png.extend(4000u32.to_be_bytes());
+        assert_eq!(dimensions(&png), Some((6000, 4000)));
+
+        // An APP1 segment to skip, then SOF2 (progressive)
+        let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xE1, 0, 6, 1, 2, 3, 4];
+        jpeg.extend([0xFF, 0xC2, 0, 17, 8]);
+        jpeg.extend(3000u16.to_be_bytes());
+        jpeg.extend(5000u16.to_be_bytes());
+        assert_eq!(dimensions(&jpeg), Some((5000, 3000)));
+
+        assert_eq!(dimensions(&[0xFF, 0xD8, 0xFF, 0xDA]), None);
+        assert_eq!(dimensions(b"GIF89a"), None);
+    }
+}
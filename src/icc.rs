@@ -0,0 +1,163 @@
+use flate2::read::ZlibDecoder;
+use lcms2::{Intent, PixelFormat, Profile, Transform};
+use opencv::prelude::*;
+use std::io::{Read, Seek, SeekFrom};
+
+// Convert an 8 bit BGR(A) image from the profile embedded in its file to
+// sRGB, which is what terminals assume. Images without a profile, or with one
+// lcms can't use, are left alone. Only the file's headers are read, the
+// pixel data is seeked over.
+pub fn convert_to_srgb<R: Read + Seek>(file: &mut R, image: &mut Mat) -> Result<(), Box<dyn std::error::Error>> {
+    let profile = match embedded_profile(file) {
+        Some(profile) => profile,
+        None => return Ok(()),
+    };
+    let profile = match Profile::new_icc(&profile) {
+        Ok(profile) => profile,
+        Err(_) => return Ok(()),
+    };
+    let format = match image.channels() {
+        3 => PixelFormat::BGR_8,
+        4 => PixelFormat::BGRA_8,
+        _ => return Ok(()),
+    };
+    let transform: Transform<u8, u8> = Transform::new(&profile, format, &Profile::new_srgb(), format, Intent::Perceptual)?;
+    transform.transform_in_place(image.data_bytes_mut()?);
+    return Ok(());
+}
+
+// The raw ICC profile embedded in a PNG, JPEG or WebP file, seeking over
+// everything else
+fn embedded_profile<R: Read + Seek>(reader: &mut R) -> Option<Vec<u8>> {
+    let mut signature = Vec::new();
+    reader.by_ref().take(12).read_to_end(&mut signature).ok()?;
+    if signature.starts_with(b"\x89PNG\r\n\x1a\n") {
+        reader.seek(SeekFrom::Start(8)).ok()?;
+        return png_profile(reader);
+    }
+    if signature.starts_with(&[0xFF, 0xD8]) {
+        reader.seek(SeekFrom::Start(2)).ok()?;
+        return jpeg_profile(reader);
+    }
+    if signature.len() == 12 && &signature[0..4] == b"RIFF" && &signature[8..12] == b"WEBP" {
+        return webp_profile(reader);
+    }
+    return None;
+}
+
+// The next `length` bytes, None if the file ends first. Lengths come from
+// the file, a broken one shouldn't allocate gigabytes up front.
+fn read_bytes<R: Read>(reader: &mut R, length: usize) -> Option<Vec<u8>> {
+    let mut data = Vec::new();
+    reader.take(length as u64).read_to_end(&mut data).ok()?;
+    return (data.len() == length).then_some(data);
+}
+
+// iCCP chunk: profile name, a NUL, the compression method (always zlib),
+// then the compressed profile
+fn png_profile<R: Read + Seek>(reader: &mut R) -> Option<Vec<u8>> {
+    loop {
+        let head = read_bytes(reader, 8)?;
+        let length = u32::from_be_bytes(head[0..4].try_into().ok()?) as usize;
+        match &head[4..8] {
+            b"iCCP" => {
+                let data = read_bytes(reader, length)?;
+                let name_end = data.iter().position(|&b| b == 0)?;
+                let mut profile = Vec::new();
+                ZlibDecoder::new(data.get(name_end + 2..)?).read_to_end(&mut profile).ok()?;
+                return Some(profile);
+            },
+            // The profile has to come before the image data
+            b"IDAT" | b"IEND" => return None,
+            _ => reader.seek(SeekFrom::Current(length as i64 + 4)).ok()?,  // Data and CRC
+        };
+    }
+}
+
+// APP2 segments tagged ICC_PROFILE, each holding a numbered piece of the profile
+fn jpeg_profile<R: Read + Seek>(reader: &mut R) -> Option<Vec<u8>> {
+    const TAG: &[u8] = b"ICC_PROFILE\0";
+    let mut pieces = Vec::new();
+    while let Some(head) = read_bytes(reader, 4) {
+        let marker = head[1];
+        // Start of scan, the headers are over
+        if head[0] != 0xFF || marker == 0xDA {
+            break;
+        }
+        let length = (u16::from_be_bytes([head[2], head[3]]) as usize).checked_sub(2)?;
+        if marker != 0xE2 {
+            reader.seek(SeekFrom::Current(length as i64)).ok()?;
+            continue;
+        }
+        let segment = read_bytes(reader, length)?;
+        if segment.starts_with(TAG) && segment.len() > TAG.len() + 2 {
+            pieces.push((segment[TAG.len()], segment[TAG.len() + 2..].to_vec()));
+        }
+    }
+    if pieces.is_empty() {
+        return None;
+    }
+    pieces.sort_by_key(|&(sequence, _)| sequence);
+    return Some(pieces.into_iter().flat_map(|(_, piece)| piece).collect());
+}
+
+// ICCP chunk of the RIFF container, stored uncompressed
+fn webp_profile<R: Read + Seek>(reader: &mut R) -> Option<Vec<u8>> {
+    loop {
+        let head = read_bytes(reader, 8)?;
+        let length = u32::from_le_bytes(head[4..8].try_into().ok()?) as usize;
+        if &head[0..4] == b"ICCP" {
+            return read_bytes(reader, length);
+        }
+        // Chunks are padded to an even length
+        reader.seek(SeekFrom::Current((length + (length & 1)) as i64)).ok()?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{write::ZlibEncoder, Compression};
+    use std::io::{Cursor, Write};
+
+    #[test]
+    fn profiles_from_each_container() {
+        let profile = b"not really a profile".to_vec();
+
+        let mut compressed = ZlibEncoder::new(Vec::new(), Compression::default());
+        compressed.write_all(&profile).unwrap();
+        let mut iccp = b"sRGB\0\0".to_vec();
+        iccp.extend(compressed.finish().unwrap());
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        png.extend((iccp.len() as u32).to_be_bytes());
+        png.extend(b"iCCP");
+        png.extend(&iccp);
+        png.extend([0; 4]);
+        assert_eq!(embedded_profile(&mut Cursor::new(png)), Some(profile.clone()));
+
+        // Split over two segments, stored out of order
+        let mut jpeg = vec![0xFF, 0xD8];
+        for (sequence, piece) in [(2u8, &profile[8..]), (1u8, &profile[..8])] {
+            let mut segment = b"ICC_PROFILE\0".to_vec();
+            segment.extend([sequence, 2]);
+            segment.extend(piece);
+            jpeg.extend([0xFF, 0xE2]);
+            jpeg.extend(((segment.len() + 2) as u16).to_be_bytes());
+            jpeg.extend(segment);
+        }
+        jpeg.extend([0xFF, 0xDA, 0, 2]);
+        assert_eq!(embedded_profile(&mut Cursor::new(jpeg)), Some(profile.clone()));
+
+        let mut webp = b"RIFF\0\0\0\0WEBP".to_vec();
+        webp.extend(b"VP8X");
+        webp.extend(10u32.to_le_bytes());
+        webp.extend([0; 10]);
+        webp.extend(b"ICCP");
+        webp.extend((profile.len() as u32).to_le_bytes());
+        webp.extend(&profile);
+        assert_eq!(embedded_profile(&mut Cursor::new(webp)), Some(profile));
+
+        assert_eq!(embedded_profile(&mut Cursor::new(b"\x89PNG\r\n\x1a\n\0\0\0\0IEND\0\0\0\0")), None);
+        assert_eq!(embedded_profile(&mut Cursor::new(b"GIF89a")), None);
+    }
+}
@@ -0,0 +1,244 @@
+use opencv::{
+    core,
+    imgcodecs,
+    imgproc,
+    prelude::*,
+    videoio,
+};
+use std::error::Error;
+use std::fs;
+
+use crate::animation;
+use crate::palette::{self, Color, ColorMode};
+
+// Longest side of the copy that dominant colors are counted on
+const SAMPLE_SIZE: i32 = 128;
+
+// Bits kept per channel when grouping similar colors, 4 gives 4096 buckets
+const BUCKET_BITS: u32 = 4;
+
+// `imprev info`: what the file is and how big, without drawing it
+pub fn print_info(path: &str) -> Result<(), Box<dyn Error>> {
+    let bytes = fs::metadata(path).map_err(|e| format!("Could not read {}: {}", path, e))?.len();
+    if animation::is_animation(path) {
+        return print_animation_info(path, bytes);
+    }
+
+    // Unchanged, so the depth and channels are the file's own
+    let image = imgcodecs::imread(path, imgcodecs::IMREAD_UNCHANGED)?;
+    if image.empty() {
+        return Err(format!("Could not read the image: {}", path).into());
+    }
+    let channels = image.channels();
+    println!("file: {}", path);
+    println!("size: {} bytes", bytes);
+    println!("dimensions: {}x{}", image.cols(), image.rows());
+    println!("channels: {}{}", channels, if channels == 4 { " (alpha)" } else { "" });
+    println!("depth: {}", depth_name(image.depth()));
+    return Ok(());
+}
+
+fn print_animation_info(path: &str, bytes: u64) -> Result<(), Box<dyn Error>> {
+    let capture = videoio::VideoCapture::from_file(path, videoio::CAP_ANY)?;
+    if !capture.is_opened()? {
+        return Err(format!("Could not open animation: {}", path).into());
+    }
+    let width = capture.get(videoio::CAP_PROP_FRAME_WIDTH)?;
+    let height = capture.get(videoio::CAP_PROP_FRAME_HEIGHT)?;
+    let frames = capture.get(videoio::CAP_PROP_FRAME_COUNT)?;
+    let fps = capture.get(videoio::CAP_PROP_FPS)?;
+    println!("file: {}", path);
+    println!("size: {} bytes", bytes);
+    println!("dimensions: {}x{}", width as i64, height as i64);
+    println!("frames: {}", frames as i64);
+    // Some containers don't know their frame rate
+    if fps > 0.0 {
+        println!("fps: {:.2}", fps);
+        println!("duration: {:.2}s", frames / fps);
+    }
+    if path.to_ascii_lowercase().ends_with(".gif") {
+        match animation::gif_loop_count(path) {
+            Some(0) => println!("loops: forever"),
+            Some(n) => println!("loops: {} times", n as u32 + 1),
+            None => println!("loops: once"),
+        }
+    }
+    return Ok(());
+}
+
+fn depth_name(depth: i32) -> &'static str {
+    match depth {
+        core::CV_8U => "8-bit unsigned",
+        core::CV_8S => "8-bit signed",
+        core::CV_16U => "16-bit unsigned",
+        core::CV_16S => "16-bit signed",
+        core::CV_32S => "32-bit signed",
+        core::CV_32F => "32-bit float",
+        core::CV_64F => "64-bit float",
+        _ => "unknown",
+    }
+}
+
+// `imprev palette`: the most common colors as swatches with their hex value
+// and share of the image. Expects the 8 bit BGR(A) that load_image gives.
+pub fn print_palette(image: &Mat, count: usize, colors: ColorMode) -> Result<(), Box<dyn Error>> {
+    for ((r, g, b), share) in dominant_colors(&sample_pixels(image)?, count) {
+        println!("{} #{:02x}{:02x}{:02x} {:>5.1}%", swatch(colors, (r, g, b)), r, g, b, share * 100.0);
+    }
+    return Ok(());
+}
+
+// --measure: the true average color and the most common color of the 256
+// color palette, for picking a representative color out of an image
+pub fn print_measure(image: &Mat, colors: ColorMode) -> Result<(), Box<dyn Error>> {
+    // Leave transparent pixels out of the average
+    let mut mask = Mat::default();
+    if image.channels() == 4 {
+        core::extract_channel(image, &mut mask, 3)?;
+    }
+    let mean = core::mean(image, &mask)?;
+    let average = (mean[2].round() as u8, mean[1].round() as u8, mean[0].round() as u8);
+    print_measurement("average", colors, average);
+
+    match most_common_index(&sample_pixels(image)?) {
+        Some(index) => print_measurement("most common", colors, Color::Indexed(index).to_rgb()),
+        None => println!("most common: none, the image is fully transparent"),
+    }
+    return Ok(());
+}
+
+// --at: the color of one pixel of an 8 bit BGR(A) image
+pub fn print_pixel(image: &Mat, (x, y): (i32, i32), colors: ColorMode) -> Result<(), Box<dyn Error>> {
+    if x < 0 || y < 0 || x >= image.cols() || y >= image.rows() {
+        return Err(format!("{},{} is outside the {}x{} image", x, y, image.cols(), image.rows()).into());
+    }
+    let (rgb, alpha) = if image.channels() == 4 {
+        let p = image.at_2d::<core::Vec4b>(y, x)?;
+        ((p[2], p[1], p[0]), p[3])
+    } else {
+        let p = image.at_2d::<core::Vec3b>(y, x)?;
+        ((p[2], p[1], p[0]), 255)
+    };
+    print_measurement(&format!("{},{}", x, y), colors, rgb);
+    println!("{:>11}: rgb({}, {}, {}), alpha {}", "", rgb.0, rgb.1, rgb.2, alpha);
+    return Ok(());
+}
+
+// Columns one --dump-palette entry takes up, gap included
+pub const ENTRY_WIDTH: i32 = 18;
+
+// --dump-palette: every color of a palette as a swatch with its index and
+// hex value, `columns` entries to a line
+pub fn print_color_table(entries: &[Color], columns: usize) {
+    for (line, chunk) in entries.chunks(columns.max(1)).enumerate() {
+        let mut out = String::new();
+        for (i, color) in chunk.iter().enumerate() {
+            let (r, g, b) = color.to_rgb();
+            let index = line * columns + i;
+            out.push_str(&format!("{} {:>3} #{:02x}{:02x}{:02x}  ", paint(*color), index, r, g, b));
+        }
+        println!("{}", out.trim_end());
+    }
+}
+
+fn print_measurement(label: &str, colors: ColorMode, (r, g, b): (u8, u8, u8)) {
+    println!(
+        "{:>11}: {} #{:02x}{:02x}{:02x} (256 color index {})",
+        label, swatch(colors, (r, g, b)), r, g, b, palette::rgb_to_256_color(r, g, b)
+    );
+}
+
+// A few cells painted in the color
+fn swatch(colors: ColorMode, (r, g, b): (u8, u8, u8)) -> String {
+    return paint(colors.quantize(r, g, b));
+}
+
+fn paint(color: Color) -> String {
+    return format!("{}    \x1B[0m", color.background());
+}
+
+// RGB of the visible pixels of a downscaled copy
+fn sample_pixels(image: &Mat) -> Result<Vec<(u8, u8, u8)>, opencv::Error> {
+    let size = image.size()?;
+    let scale = (SAMPLE_SIZE as f64 / size.width.max(size.height) as f64).min(1.0);
+    let mut small = Mat::default();
+    imgproc::resize(image, &mut small, core::Size::new(0, 0), scale, scale, imgproc::INTER_AREA)?;
+
+    let mut pixels = Vec::with_capacity(small.total());
+    let has_alpha = small.channels() == 4;
+    for r in 0..small.rows() {
+        for c in 0..small.cols() {
+            if has_alpha {
+                let p = small.at_2d::<core::Vec4b>(r, c)?;
+                // Transparent pixels aren't part of the picture
+                if p[3] > 0 {
+                    pixels.push((p[2], p[1], p[0]));
+                }
+            } else {
+                let p = small.at_2d::<core::Vec3b>(r, c)?;
+                pixels.push((p[2], p[1], p[0]));
+            }
+        }
+    }
+    return Ok(pixels);
+}
+
+// The 256 color palette entry the most pixels map to
+fn most_common_index(pixels: &[(u8, u8, u8)]) -> Option<u8> {
+    let mut counts = [0usize; 256];
+    for &(r, g, b) in pixels {
+        counts[palette::rgb_to_256_color(r, g, b) as usize] += 1;
+    }
+    return (0..=255u8).filter(|&i| counts[i as usize] > 0).max_by_key(|&i| counts[i as usize]);
+}
+
+// Group pixels into coarse buckets and return the average color of the
+// biggest ones along with the fraction of pixels each covers
+fn dominant_colors(pixels: &[(u8, u8, u8)], count: usize) -> Vec<((u8, u8, u8), f64)> {
+    let shift = 8 - BUCKET_BITS;
+    let mut buckets = vec![(0u64, [0u64; 3]); 1 << (3 * BUCKET_BITS)];
+    for &(r, g, b) in pixels {
+        let index = ((r >> shift) as usize) << (2 * BUCKET_BITS) | ((g >> shift) as usize) << BUCKET_BITS | (b >> shift) as usize;
+        let bucket = &mut buckets[index];
+        bucket.0 += 1;
+        bucket.1[0] += r as u64;
+        bucket.1[1] += g as u64;
+        bucket.1[2] += b as u64;
+    }
+
+    let mut ranked: Vec<_> = buckets.into_iter().filter(|bucket| bucket.0 > 0).collect();
+    ranked.sort_by_key(|bucket| std::cmp::Reverse(bucket.0));
+    return ranked
+        .into_iter()
+        .take(count)
+        .map(|(n, sum)| {
+            let average = |total: u64| (total / n) as u8;
+            ((average(sum[0]), average(sum[1]), average(sum[2])), n as f64 / pixels.len() as f64)
+        })
+        .collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dominant_colors_by_share() {
+        let mut pixels = vec![(250, 0, 0); 6];
+        pixels.extend([(0, 0, 250), (0, 0, 252), (0, 0, 254)]);
+        pixels.push((0, 250, 0));
+        let colors = dominant_colors(&pixels, 2);
+        assert_eq!(colors.len(), 2);
+        assert_eq!(colors[0], ((250, 0, 0), 0.6));
+        assert_eq!(colors[1].0, (0, 0, 252));
+        assert!((colors[1].1 - 0.3).abs() < 1e-9);
+        assert!(dominant_colors(&[], 3).is_empty());
+    }
+
+    #[test]
+    fn most_common_palette_entry() {
+        let pixels = [(0, 0, 255), (255, 0, 0), (255, 20, 5)];
+        assert_eq!(most_common_index(&pixels), Some(196));
+        assert_eq!(most_common_index(&[]), None);
+    }
+}
@@ -0,0 +1,2514 @@
+// Terminal image previews. The imprev binary is run(), programs of their own
+// can turn images into frames of escape sequences with render_frame() and
+// render_animation() and write them wherever they like.
+extern crate opencv;
+extern crate term_size;
+
+pub mod animation;
+mod annotate;
+mod ascii;
+mod bands;
+mod cli;
+mod crop;
+#[cfg(test)]
+mod decode;
+pub mod density;
+pub mod dither;
+mod export;
+mod faces;
+pub mod filters;
+mod header;
+#[cfg(feature = "icc")]
+mod icc;
+mod inspect;
+mod metadata;
+pub mod palette;
+mod profile;
+pub mod source;
+pub mod stream;
+mod watch;
+
+use opencv::{
+    imgcodecs,
+    core,
+    prelude::*,
+    Result,
+    imgproc
+};
+use std::io::{self, IsTerminal, Write};
+use libc::{ioctl, winsize, STDOUT_FILENO, TIOCGWINSZ};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use source::FrameSource;
+use signal_hook::consts::signal::*;
+use signal_hook::iterator::Signals;
+use animation::Playback;
+use density::Density;
+use palette::{Color, ColorMode, Metric, Palette};
+
+fn clear_screen() {
+    print!("\x1B[2J\x1B[1;1H");
+    io::stdout().flush().unwrap();
+}
+
+// Signals::new can fail in sandboxes, and --no-signals skips them altogether
+fn install_signals(signals: &[i32], options: &Options) -> Option<Signals> {
+    if options.no_signals {
+        return None;
+    }
+    match Signals::new(signals) {
+        Ok(signals) => return Some(signals),
+        Err(e) => {
+            log_verbose(options, &format!("Could not install signal handlers, carrying on without them: {}", e));
+            return None;
+        },
+    }
+}
+
+// Draw the image again at the terminal's new size
+fn redraw_resized(image: &Mutex<Mat>, options: &Options) {
+    let image = image.lock().unwrap();
+    clear_screen();
+    if let Err(e) = render(&image, image_dims(&image), options) {
+        eprintln!("Error: {}", e);
+    }
+}
+
+// Undo anything we changed about the terminal, before exiting
+fn restore_terminal(options: &Options) {
+    print!("\x1B[0m");
+    if options.scroll_region {
+        print!("\x1B[r");
+    }
+    println!();
+    leave_alt_screen();
+    restore_title();
+    io::stdout().flush().unwrap();
+}
+
+// Set while drawing on the alternate screen for --clear-on-exit
+static ALT_SCREEN: AtomicBool = AtomicBool::new(false);
+
+// Draw on a screen of our own, leaving it brings back what was there before
+fn enter_alt_screen() {
+    print!("\x1B[?1049h\x1B[H");
+    io::stdout().flush().unwrap();
+    ALT_SCREEN.store(true, Ordering::SeqCst);
+}
+
+// Set once --title has saved the terminal's own title
+static TITLE_SET: AtomicBool = AtomicBool::new(false);
+
+// --title: name the window or tab after the image (or the given text). The
+// old title goes on the terminal's title stack to be put back on exit.
+fn show_title(options: &Options, path: &str) {
+    let title = match &options.title {
+        Some(Some(title)) => title.clone(),
+        Some(None) => Path::new(path).file_name().map_or(path.into(), |name| name.to_string_lossy().into_owned()),
+        None => return,
+    };
+    if !TITLE_SET.swap(true, Ordering::SeqCst) {
+        print!("\x1B[22;0t");
+    }
+    // A control character would end the sequence early
+    let title: String = title.chars().filter(|c| !c.is_control()).collect();
+    print!("\x1B]0;{}\x07", title);
+    io::stdout().flush().unwrap();
+}
+
+fn restore_title() {
+    if TITLE_SET.swap(false, Ordering::SeqCst) {
+        print!("\x1B[23;0t");
+        io::stdout().flush().unwrap();
+    }
+}
+
+fn leave_alt_screen() {
+    if ALT_SCREEN.swap(false, Ordering::SeqCst) {
+        print!("\x1B[?1049l");
+        io::stdout().flush().unwrap();
+    }
+}
+
+// Get ready to draw the next frame over the last one. --home-only skips the
+// clear to avoid flicker, render() erases whatever the new frame didn't cover.
+fn start_redraw(options: &Options) {
+    if options.scrollback_friendly {
+        // Frames just follow each other down the page
+        return;
+    }
+    if options.home_only {
+        print!("\x1B[H");
+        io::stdout().flush().unwrap();
+    } else {
+        clear_screen();
+    }
+}
+
+// With stdout going somewhere that isn't a terminal and no terminal to ask
+// either (cron, CI) the output is drawn at PIPED_SIZE
+fn get_terminal_size() -> Result<(i32, i32), &'static str> {
+    return match get_terminal_geometry() {
+        Err(_) if !io::stdout().is_terminal() => Ok(PIPED_SIZE),
+        geometry => geometry.map(|(cells, _)| cells),
+    };
+}
+
+// Columns and rows of the terminal, and its width and height in pixels on
+// terminals that fill them in
+type Geometry = ((i32, i32), Option<(i32, i32)>);
+
+fn get_terminal_geometry() -> Result<Geometry, &'static str> {
+    let mut size: winsize = unsafe { std::mem::zeroed() };
+    if unsafe { ioctl(STDOUT_FILENO, TIOCGWINSZ, &mut size) } == 0 && size.ws_col > 0 && size.ws_row > 0 {
+        let pixels = (size.ws_xpixel > 0 && size.ws_ypixel > 0).then_some((size.ws_xpixel as i32, size.ws_ypixel as i32));
+        return Ok(((size.ws_col as i32, size.ws_row as i32), pixels));
+    }
+    // Stdout isn't the terminal, it may still be on stdin or stderr
+    match term_size::dimensions() {
+        Some((w, h)) => Ok(((w as i32, h as i32), None)),
+        None => Err("Unable to determine terminal size"),
+    }
+}
+
+// How much to shrink the height of the image for it to keep its proportions
+// in cells: the terminal's own cell shape when it reports its pixel size,
+// DEFAULT_HEIGHT_RESCALE (cells twice as tall as wide) otherwise
+fn height_rescale(options: &Options) -> f32 {
+    return options.cell_aspect.unwrap_or(DEFAULT_HEIGHT_RESCALE);
+}
+
+// Width over height of one cell, None when the pixel size is too unlikely
+// to be the font's (some terminals report the screen's, or made up values)
+fn cell_aspect((columns, rows): (i32, i32), (width, height): (i32, i32)) -> Option<f32> {
+    let aspect = (width as f32 / columns as f32) / (height as f32 / rows as f32);
+    return (0.2..=2.0).contains(&aspect).then_some(aspect);
+}
+
+// The size --lock-size keeps drawing at, the terminal's when it first drew
+static LOCKED_SIZE: Mutex<Option<(i32, i32)>> = Mutex::new(None);
+
+// The terminal size to draw at
+fn drawing_size(options: &Options) -> Result<(i32, i32), &'static str> {
+    if !options.lock_size {
+        return get_terminal_size();
+    }
+    let mut locked = LOCKED_SIZE.lock().unwrap();
+    if let Some(size) = *locked {
+        return Ok(size);
+    }
+    let size = get_terminal_size()?;
+    *locked = Some(size);
+    return Ok(size);
+}
+
+// Work out how many colors the terminal supports from its terminfo entry.
+// COLORTERM is how most truecolor terminals announce themselves, since few
+// terminfo entries report 16M colors. Falls back to 256 colors.
+fn detect_color_mode() -> ColorMode {
+    if matches!(env::var("COLORTERM").as_deref(), Ok("truecolor") | Ok("24bit")) {
+        return ColorMode::Truecolor;
+    }
+    let database = match terminfo::Database::from_env() {
+        Ok(database) => database,
+        Err(_) => return ColorMode::Xterm256,
+    };
+    let truecolor = database.get::<terminfo::capability::TrueColor>().is_some_and(|tc| tc.0)
+        || database.raw("RGB").is_some();
+    if truecolor {
+        return ColorMode::Truecolor;
+    }
+    match database.get::<terminfo::capability::MaxColors>() {
+        Some(colors) => ColorMode::from_color_count(colors.0),
+        None => ColorMode::Xterm256,
+    }
+}
+
+fn scale_image(terminal_wh:(i32,i32), image_wh:(i32,i32), height_scale: f32) -> (i32, i32){
+    let image_ar: f32 = image_wh.0 as f32 * (1.0/height_scale as f32) as f32 / image_wh.1 as f32 ;
+    let termi_ar: f32 = terminal_wh.0 as f32   / terminal_wh.1 as f32;
+
+    // Very long or very tall images still get one cell across, resize can't do zero
+    if image_ar > termi_ar {
+        let new_height = terminal_wh.0 as f32 / image_ar;
+        return (terminal_wh.0, (new_height as i32).max(1))
+    } else {
+        let new_width = terminal_wh.1 as f32 * image_ar;
+        return ((new_width as i32).max(1), terminal_wh.1)
+    }
+}
+
+// Diagnostics on stderr with --verbose
+fn log_verbose(options: &Options, message: &str) {
+    if options.verbose && io::stderr().is_terminal() {
+        eprintln!("{}", message);
+    }
+}
+
+// Report how long a stage took
+fn log_timing(options: &Options, stage: &str, started: Instant) {
+    profile::record(stage, started.elapsed());
+    log_verbose(options, &format!("{:>10}: {:.2?}", stage, started.elapsed()));
+}
+
+// Shrink both dimensions by the same factor until width * height fits the budget
+fn clamp_cells(dimensions: (i32, i32), max_cells: i64) -> (i32, i32) {
+    let cells = dimensions.0 as i64 * dimensions.1 as i64;
+    if cells <= max_cells {
+        return dimensions;
+    }
+    let factor = (max_cells as f64 / cells as f64).sqrt();
+    let height = ((dimensions.1 as f64 * factor).floor() as i32).max(1);
+    // A side stuck at one cell means the other has to give up the difference
+    let width = ((dimensions.0 as f64 * factor).floor() as i32).min(dimensions.0);
+    let width = width.clamp(1, (max_cells / height as i64).clamp(1, i32::MAX as i64) as i32);
+    let height = height.min((max_cells / width as i64).clamp(1, i32::MAX as i64) as i32);
+    return (width, height);
+}
+
+// Map a pixel to a cell color using the custom palette or the terminal's colors
+fn quantize(options: &Options, red: u8, green: u8, blue: u8) -> Color {
+    match &options.palette {
+        Some(palette) => {
+            let (r, g, b) = palette.nearest_with(red, green, blue, options.metric());
+            Color::Rgb(r, g, b)
+        },
+        None => options.colors.quantize_with(red, green, blue, options.metric()),
+    }
+}
+
+// Center the colormap in a frame of the given size, filling the bars around it
+fn letterbox(colormap: Vec<Vec<Color>>, frame: (i32, i32), fill: Color) -> Vec<Vec<Color>> {
+    let base = vec![vec![fill; frame.0.max(0) as usize]; frame.1.max(0) as usize];
+    return center_over(base, colormap);
+}
+
+// Where --grid draws its lines
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Grid {
+    Every(u32),  // Every this many cells, across and down
+    Thirds,      // The rule of thirds, two lines each way
+}
+
+impl Grid {
+    // --grid value: a number of cells or "thirds"
+    pub fn parse(value: &str) -> Result<Grid, String> {
+        if value == "thirds" {
+            return Ok(Grid::Thirds);
+        }
+        match value.parse::<u32>() {
+            Ok(cells) if cells > 0 => Ok(Grid::Every(cells)),
+            _ => Err(format!("expected a number of cells or 'thirds': {}", value)),
+        }
+    }
+
+    // Whether the cell at `index` of `length` cells is on a line
+    fn on_line(self, index: usize, length: usize) -> bool {
+        match self {
+            Grid::Every(cells) => index > 0 && index.is_multiple_of(cells as usize),
+            Grid::Thirds => length >= 3 && (index == length / 3 || index == length * 2 / 3),
+        }
+    }
+}
+
+// Blend the --grid lines halfway into the cells they cross. The colormap is
+// in pixels, `cell` says how many of them make up a cell across and down.
+fn overlay_grid(mut colormap: Vec<Vec<Color>>, grid: Grid, cell: (i32, i32), options: &Options) -> Vec<Vec<Color>> {
+    let (across, down) = (cell.0.max(1) as usize, cell.1.max(1) as usize);
+    let columns = colormap.first().map_or(0, |row| row.len()).div_ceil(across);
+    let rows = colormap.len().div_ceil(down);
+    let line = options.grid_color.unwrap_or(DEFAULT_GRID_COLOR);
+    // Exact colors stay exact, everything else goes back through the palette
+    let exact = options.adaptive_palette && options.palette.is_none();
+    for (r, row) in colormap.iter_mut().enumerate() {
+        let on_row = grid.on_line(r / down, rows);
+        for (c, color) in row.iter_mut().enumerate() {
+            if !on_row && !grid.on_line(c / across, columns) {
+                continue;
+            }
+            // Transparent cells show the dark terminal background
+            let under = if *color == Color::Transparent { (0, 0, 0) } else { color.to_rgb() };
+            let mix = |a: u8, b: u8| ((a as u16 + b as u16) / 2) as u8;
+            let (red, green, blue) = (mix(under.0, line.0), mix(under.1, line.1), mix(under.2, line.2));
+            *color = if exact { Color::Rgb(red, green, blue) } else { quantize(options, red, green, blue) };
+        }
+    }
+    return colormap;
+}
+
+// Cut a colormap wider than `width` into strips that fit and stack them,
+// `gap` blank rows apart. Strips are padded out with transparent cells, to
+// the full width and down to a multiple of `gap` rows, so with one pixel row
+// of gap per row of a cell, no cell straddles two strips.
+fn fold(colormap: Vec<Vec<Color>>, width: usize, gap: usize) -> Vec<Vec<Color>> {
+    let full_width = colormap.first().map_or(0, |row| row.len());
+    if width == 0 || full_width <= width {
+        return colormap;
+    }
+    let height = colormap.len().div_ceil(gap.max(1)) * gap.max(1);
+    let blank = vec![Color::Transparent; width];
+    let mut folded = Vec::new();
+    for start in (0..full_width).step_by(width) {
+        if start > 0 {
+            folded.resize(folded.len() + gap, blank.clone());
+        }
+        for row in &colormap {
+            let mut strip = row[start..(start + width).min(full_width)].to_vec();
+            strip.resize(width, Color::Transparent);
+            folded.push(strip);
+        }
+        folded.resize(folded.len() + height - colormap.len(), blank.clone());
+    }
+    return folded;
+}
+
+// Where a colormap of this size sits when centered in the frame
+fn centered_origin(frame: (i32, i32), size: (i32, i32)) -> (i32, i32) {
+    return (((frame.0 - size.0) / 2).max(0), ((frame.1 - size.1) / 2).max(0));
+}
+
+// Paste the colormap into the middle of a bigger one
+fn center_over(base: Vec<Vec<Color>>, colormap: Vec<Vec<Color>>) -> Vec<Vec<Color>> {
+    let frame = (base.first().map_or(0, |row| row.len()) as i32, base.len() as i32);
+    let size = (colormap.first().map_or(0, |row| row.len()) as i32, colormap.len() as i32);
+    let (left, top) = centered_origin(frame, size);
+    let (left, top) = (left as usize, top as usize);
+
+    let mut framed = base;
+    for (r, row) in colormap.into_iter().enumerate() {
+        if let Some(target) = framed.get_mut(top + r) {
+            for (c, color) in row.into_iter().enumerate() {
+                if let Some(cell) = target.get_mut(left + c) {
+                    *cell = color;
+                }
+            }
+        }
+    }
+    return framed;
+}
+
+// Stretch a colormap to a bigger size by repeating cells
+fn upscale_nearest(colormap: &[Vec<Color>], dimensions: (i32, i32)) -> Vec<Vec<Color>> {
+    let height = colormap.len();
+    let width = colormap.first().map_or(0, |row| row.len());
+    if width == 0 || height == 0 {
+        return Vec::new();
+    }
+    return (0..dimensions.1 as usize)
+        .map(|r| {
+            let row = &colormap[(r * height / dimensions.1 as usize).min(height - 1)];
+            (0..dimensions.0 as usize)
+                .map(|c| row[(c * width / dimensions.0 as usize).min(width - 1)])
+                .collect()
+        })
+        .collect();
+}
+
+// Size of one tile: the image at one pixel per cell (squashed vertically like
+// everything else), or fitted to the terminal if it doesn't fit as is
+fn tile_size(terminal_wh: (i32, i32), image_wh: (i32, i32), height_scale: f32) -> (i32, i32) {
+    let native = (image_wh.0, ((image_wh.1 as f32 * height_scale).round() as i32).max(1));
+    if native.0 <= terminal_wh.0 && native.1 <= terminal_wh.1 {
+        return native;
+    }
+    return scale_image(terminal_wh, image_wh, height_scale);
+}
+
+// Repeat the tile across the frame. The offset shifts the pattern by that
+// many cells and the repeat counts stop it early, leaving the rest blank.
+fn tile(tile: &[Vec<Color>], frame: (i32, i32), offset: (i32, i32), repeat: (Option<u32>, Option<u32>)) -> Vec<Vec<Color>> {
+    let tile_height = tile.len() as i32;
+    let tile_width = tile.first().map_or(0, |row| row.len()) as i32;
+    if tile_width == 0 || tile_height == 0 {
+        return Vec::new();
+    }
+    let limit = |count: Option<u32>, size: i32, available: i32| {
+        count.map_or(available, |n| (n as i64 * size as i64).min(available as i64) as i32)
+    };
+    let used_width = limit(repeat.0, tile_width, frame.0);
+    let used_height = limit(repeat.1, tile_height, frame.1);
+
+    let mut tiled = Vec::with_capacity(frame.1.max(0) as usize);
+    for r in 0..frame.1 {
+        let mut row = vec![Color::Transparent; frame.0.max(0) as usize];
+        if r < used_height {
+            let source = &tile[(r + offset.1).rem_euclid(tile_height) as usize];
+            for c in 0..used_width {
+                row[c as usize] = source[(c + offset.0).rem_euclid(tile_width) as usize];
+            }
+        }
+        tiled.push(row);
+    }
+    return tiled;
+}
+
+// Scale the image to cover the whole frame of cells, cropping whatever
+// overflows evenly from both sides
+fn fill_resize(image: &Mat, frame: (i32, i32), height_scale: f32) -> Result<Mat, opencv::Error> {
+    let (width, height) = image_dims(image);
+    // Width over height of the frame in pixels, cells are taller than wide
+    let aspect = frame.0 as f32 * height_scale / frame.1 as f32;
+    let (crop_width, crop_height) = if width as f32 / height as f32 > aspect {
+        (((height as f32 * aspect).round() as i32).clamp(1, width), height)
+    } else {
+        (width, ((width as f32 / aspect).round() as i32).clamp(1, height))
+    };
+    let rect = core::Rect::new((width - crop_width) / 2, (height - crop_height) / 2, crop_width, crop_height);
+    let cropped = Mat::roi(image, rect)?;
+    let mut filled = Mat::default();
+    imgproc::resize(&cropped, &mut filled, core::Size::new(frame.0, frame.1), 0.0, 0.0, imgproc::INTER_AREA)?;
+    return Ok(filled);
+}
+
+fn build_colormap(image: &Mat, dimensions: (i32, i32), options: &Options) -> Result<Vec<Vec<Color>>, opencv::Error> {
+    return build_colormap_over(image, dimensions, options, None);
+}
+
+// Same as build_colormap, but (partly) transparent pixels are blended onto
+// the matching pixels of a BGR background instead of the terminal's own
+fn build_colormap_over(image: &Mat, dimensions: (i32, i32), options: &Options, background: Option<&Mat>) -> Result<Vec<Vec<Color>>, opencv::Error> {
+    // Mosaic first, the blocks are in pixels of the source
+    let pixelated;
+    let image = match options.pixelate {
+        Some(block) => {
+            pixelated = filters::pixelate(image, block)?;
+            &pixelated
+        },
+        None => image,
+    };
+
+    // Resize the image to the new dimensions
+    let started = Instant::now();
+    let mut resized = Mat::default();
+    imgproc::resize(
+        &image, 
+        &mut resized, 
+        core::Size::new(dimensions.0, dimensions.1), 
+        0.0, 0.0, imgproc::INTER_AREA  // Each cell is the average of the pixels it covers
+    );
+    log_timing(options, "resize", started);
+
+    // Color filters run on the small image, it's cheaper than the source
+    if let Some(cvd) = options.cvd {
+        resized = filters::simulate_cvd(&resized, cvd)?;
+    }
+    // Edges are found on the full image where the detail still is, then
+    // shrunk with it so each pixel says how much of what it covers is sharp
+    let peaking = if options.focus_peaking {
+        let mask = filters::focus_mask(image, options.peaking_threshold, options.luma())?;
+        let mut shrunk = Mat::default();
+        imgproc::resize(&mask, &mut shrunk, core::Size::new(dimensions.0, dimensions.1), 0.0, 0.0, imgproc::INTER_AREA)?;
+        let (red, green, blue) = options.peaking_color.unwrap_or(DEFAULT_PEAKING_COLOR);
+        let exact = options.adaptive_palette && options.palette.is_none();
+        Some((shrunk, if exact { Color::Rgb(red, green, blue) } else { quantize(options, red, green, blue) }))
+    } else {
+        None
+    };
+    let background = match background {
+        Some(background) if image_dims(background) != dimensions => {
+            let mut scaled = Mat::default();
+            imgproc::resize(background, &mut scaled, core::Size::new(dimensions.0, dimensions.1), 0.0, 0.0, imgproc::INTER_AREA)?;
+            Some(scaled)
+        },
+        Some(background) => Some(background.try_clone()?),
+        None => None,
+    };
+
+    // Create a map of colors
+    let started = Instant::now();
+    let rows = resized.rows() as i32;
+    let cols = resized.cols() as i32;
+    let mut array = Vec::with_capacity(rows as usize);
+
+    // The adaptive palette is worked out from the finished cells, until
+    // then they hold their exact color
+    let adaptive = options.adaptive_palette && options.palette.is_none();
+    let quantize = |red: u8, green: u8, blue: u8| {
+        if adaptive { Color::Rgb(red, green, blue) } else { quantize(options, red, green, blue) }
+    };
+
+    let mut ditherer = dither::Ditherer::new(options.dither_type, cols as usize);
+    let mut quantize = |r: i32, c: i32, red: u8, green: u8, blue: u8| ditherer.pick(r, c, (red, green, blue), quantize);
+
+    // Squares of the checkerboard in pixels, half as many rows as columns
+    // since cells are about twice as tall as wide
+    let checker = options.checkerboard.then(|| {
+        let (across, down) = options.density.cell_pixels();
+        let size = options.checker_size.unwrap_or(DEFAULT_CHECKER_SIZE) as i32;
+        ((size * across, (size * down / 2).max(1)), options.checker_colors.unwrap_or(DEFAULT_CHECKER_COLORS))
+    });
+
+    // Loop over everything and convert BGR info to a Color Index
+    let has_alpha = resized.channels() == 4;
+    for r in 0..rows {
+        let mut row = vec![Color::Indexed(0); cols as usize];  // Initialize each row with zeroes (or some other value)
+        for c in 0..cols {
+            // Returns in BGR, not RGB
+            let (blue, green, red, alpha) = if has_alpha {
+                let p = resized.at_2d::<core::Vec4b>(r, c)?;
+                (p[0], p[1], p[2], p[3])
+            } else {
+                let p = resized.at_2d::<core::Vec3b>(r, c)?;
+                (p[0], p[1], p[2], 255)
+            };
+            let under = match &background {
+                Some(background) => {
+                    let p = background.at_2d::<core::Vec3b>(r, c)?;
+                    Some((p[2], p[1], p[0]))
+                },
+                None => checker.map(|(tile, colors)| checker_color(r, c, tile, colors)),
+            };
+            if let Some(under) = under {
+                let blend = |v: u8, u: u8| ((v as u16 * alpha as u16 + u as u16 * (255 - alpha as u16)) / 255) as u8;
+                row[c as usize] = quantize(r, c, blend(red, under.0), blend(green, under.1), blend(blue, under.2));
+                continue;
+            }
+            if alpha == 0 {
+                row[c as usize] = Color::Transparent;
+                continue;
+            }
+            // Blend partially covered pixels onto a dark terminal background
+            let blend = |v: u8| (v as u16 * alpha as u16 / 255) as u8;
+            row[c as usize] = quantize(r, c, blend(red), blend(green), blend(blue));
+        }
+        array.push(row);
+    }
+    if let Some((mask, highlight)) = &peaking {
+        for (r, row) in array.iter_mut().enumerate() {
+            for (c, color) in row.iter_mut().enumerate() {
+                if *mask.at_2d::<u8>(r as i32, c as i32)? >= PEAKING_COVERAGE {
+                    *color = *highlight;
+                }
+            }
+        }
+    }
+    if adaptive {
+        let size = options.max_colors.map_or(palette::ADAPTIVE_PALETTE_SIZE, |n| n as usize);
+        snap_to_adaptive_palette(&mut array, size, options.metric());
+    }
+    log_timing(options, "colormap", started);
+    return Ok(array);
+}
+
+// The color of the checkerboard at row r, column c, in `tile` sized squares
+// alternating between the two colors from the top left
+fn checker_color(r: i32, c: i32, (width, height): (i32, i32), (first, second): cli::ColorPair) -> (u8, u8, u8) {
+    return if (r / height + c / width) % 2 == 0 { first } else { second };
+}
+
+// Replace the exact colors of the cells with a palette made for them
+fn snap_to_adaptive_palette(colormap: &mut [Vec<Color>], size: usize, metric: Metric) {
+    let mut pixels = Vec::new();
+    for color in colormap.iter().flatten() {
+        if let Color::Rgb(r, g, b) = *color {
+            pixels.push((r, g, b));
+        }
+    }
+    let palette = palette::median_cut(&pixels, size);
+    for color in colormap.iter_mut().flatten() {
+        if let Color::Rgb(r, g, b) = *color {
+            let (r, g, b) = palette.nearest_with(r, g, b, metric);
+            *color = Color::Rgb(r, g, b);
+        }
+    }
+}
+
+// Printed once and never redrawn, so nothing but colors and newlines (and
+// the --link around them)
+fn print_bitmap(colormap: Vec<Vec<Color>>, dimensions: (i32, i32), link: Option<&str>) {
+    let mut out = String::new();
+    write_bitmap(&mut out, &colormap, dimensions, Placement::Plain);
+    wrap_link(&mut out, 0, link);
+    if let Err(e) = write_out(&out, Buffering::Frame) {
+        eprintln!("Error: {}", e);
+    }
+}
+
+// --link: turn everything written from `start` on into an OSC 8 hyperlink,
+// clicking any cell of it opens the URL. Terminals without them skip the
+// sequences.
+fn wrap_link(out: &mut String, start: usize, link: Option<&str>) {
+    if let Some(url) = link {
+        out.insert_str(start, &format!("\x1B]8;;{}\x07", url));
+        out.push_str("\x1B]8;;\x07");
+    }
+}
+
+// How a frame goes out to stdout
+#[derive(Clone, Copy, Debug, Default, PartialEq, clap::ValueEnum)]
+pub enum Buffering {
+    #[default]
+    Frame,  // All at once, a single write for the whole frame
+    Line,   // A write per row, so a slow link shows the image coming in
+}
+
+// Stdout on its own flushes at every newline, which is a write per row
+fn write_out(text: &str, buffering: Buffering) -> io::Result<()> {
+    let stdout = io::stdout();
+    match buffering {
+        Buffering::Frame => {
+            let mut out = io::BufWriter::with_capacity(text.len().max(1), stdout.lock());
+            out.write_all(text.as_bytes())?;
+            out.flush()?;
+        },
+        Buffering::Line => {
+            let mut out = stdout.lock();
+            for line in text.split_inclusive('\n') {
+                out.write_all(line.as_bytes())?;
+                out.flush()?;
+            }
+        },
+    }
+    return Ok(());
+}
+
+// Where the rows of a frame go: each under the last, or each moved to its
+// place in a pane with absolute cursor positioning
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Placement {
+    #[default]
+    Flow,
+    At(i32, i32),  // Column and row of the top left cell, from 0
+    Plain,         // Like Flow but only colors and newlines, safe to log or paste
+}
+
+impl Placement {
+    pub(crate) fn start_row(self, out: &mut String, row: usize) {
+        if let Placement::At(x, y) = self {
+            out.push_str(&format!("\x1B[{};{}H", y + row as i32 + 1, x + 1));
+        }
+    }
+
+    pub(crate) fn end_row(self, out: &mut String) {
+        match self {
+            // Erase what's left of the line, in case the last frame was wider
+            Placement::Flow => out.push_str("\x1B[K\n"),
+            // Whatever is right of the pane belongs to someone else
+            Placement::At(..) => {},
+            Placement::Plain => out.push('\n'),
+        }
+    }
+
+    // Leave `cells` cells blank
+    pub(crate) fn skip(self, out: &mut String, cells: usize) {
+        match self {
+            Placement::Plain => out.push_str(&" ".repeat(cells)),
+            // Jumping over them shows what was there before
+            _ => out.push_str(&format!("\x1B[{}C", cells)),
+        }
+    }
+}
+
+// Append the escape sequences that paint one cell per color
+fn write_bitmap(out: &mut String, colormap: &[Vec<Color>], dimensions: (i32, i32), placement: Placement) {
+    for r in 0..dimensions.1 {
+        placement.start_row(out, r as usize);
+        let mut skipped = 0;  // Transparent cells to jump over before the next paint
+        for c in 0..dimensions.0 {
+            let color = colormap[r as usize][c as usize];
+            if color == Color::Transparent {
+                skipped += 1;
+                continue;
+            }
+            if skipped > 0 {
+                placement.skip(out, skipped);
+                skipped = 0;
+            }
+            out.push_str(&format!("{} \x1B[0m", color.background()));
+        }
+        placement.end_row(out);
+    }
+    // Whatever comes next starts on a fresh line with no colors left set
+    out.push_str("\x1B[0m");
+}
+
+
+// Re-Render the image. Called by SIGWINCH. Failing while watching leaves
+// the last frame up and waits for the next one, a single draw exits with
+// the error's code.
+fn render(image: &Mat, input_dims: (i32, i32), options: &Options) -> Result<(), Box<dyn std::error::Error>> {
+    // Get Terminal Size
+    let terminal = match drawing_size(options) {
+        Ok((h, w)) => (h, w),
+        Err(e) if !options.watching() => fail(EXIT_TERMINAL, format!("Error getting terminal size: {}", e)),
+        Err(e) => {
+            eprintln!("Error getting terminal size: {}", e);
+            return Ok(());
+        }
+    };
+    let (placement, size) = frame_area(terminal, options);
+    if options.scroll_region && SCROLL_REGION_SET.swap(false, Ordering::SeqCst) {
+        // Back to the whole screen while drawing, this also homes the cursor
+        print!("\x1B[r");
+    }
+
+    let started = Instant::now();
+    let mut rendered = match render_sized(image, input_dims, size, placement, options) {
+        Ok(rendered) => rendered,
+        Err(e) if !options.watching() => fail(EXIT_BAD_IMAGE, format!("Error: {}", e)),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return finish_frame(options, 0, None);
+        }
+    };
+    add_extras(&mut rendered, placement, started, options);
+    let frame = rendered.frame;
+    let started = Instant::now();
+    clear_if_shrunk(options, frame.size);
+    write_out(&frame.text, options.buffering)?;
+    log_timing(options, "print", started);
+    let rows = match placement {
+        Placement::At(_, y) => {
+            // Carry on under the rectangle like after a frame that flowed there
+            print!("\x1B[{};1H", y + frame.size.1 + 1);
+            y + frame.size.1
+        },
+        _ => frame.size.1,
+    };
+    return finish_frame(options, rows, rendered.detections);
+}
+
+// What the CLI does with a frame besides printing it, left out of the
+// library: the --save-png and --record copies and the corner panels
+fn add_extras(rendered: &mut Rendered, placement: Placement, started: Instant, options: &Options) {
+    let colormap = &rendered.colormap;
+    if !colormap.is_empty() {
+        if let Some(path) = &options.save_png {
+            let cell_size = options.cell_size.unwrap_or(export::DEFAULT_CELL_SIZE);
+            if let Err(e) = export::save_png(colormap, path, cell_size, rendered.cell_pixels) {
+                eprintln!("Error saving {}: {}", path, e);
+            }
+        }
+        if options.record.is_some() {
+            record_frame(colormap, rendered.cell_pixels, options);
+        }
+    }
+    if !rendered.panels {
+        return;
+    }
+    // The color of a cell is taken from its top left pixel
+    let (across, down) = rendered.cell_pixels;
+    let under = |c: i32, r: i32| {
+        let row = colormap.get((r * down) as usize);
+        return row.and_then(|row| row.get((c * across) as usize)).copied().unwrap_or(Color::Transparent);
+    };
+    let (text, size) = (&mut rendered.frame.text, rendered.frame.size);
+    write_shooting_info(text, size, placement, under, options);
+    write_fps(text, size, placement, under, started, options);
+}
+
+// Whether a frame of this many cells is too small to draw the image in
+fn too_small((width, height): (i32, i32)) -> bool {
+    return width < MIN_FRAME_SIZE.0 || height < MIN_FRAME_SIZE.1;
+}
+
+// Where a frame goes on a terminal of this size and how many cells it gets
+fn frame_area((width, height): (i32, i32), options: &Options) -> (Placement, (i32, i32)) {
+    let (width, height) = (width.max(1), height.max(1));
+    let (placement, size) = match options.contain_within {
+        // Exactly where it was asked for, as much of it as is on screen
+        Some((x, y, w, h)) => {
+            let (x, y) = (x.min(width - 1), y.min(height - 1));
+            (Placement::At(x, y), (w.min(width - x), h.min(height - y)))
+        },
+        // Leave the bottom rows for the status lines and the line the cursor
+        // ends up on, a full height image would scroll its top row away
+        None if options.fullscreen => (Placement::Flow, (width, (height - status_lines(options) - 1).max(1))),
+        // Inline the image only gets part of the screen, the output above it stays in view
+        None => (Placement::Flow, (width, ((height as f32 * INLINE_HEIGHT_SHARE) as i32).max(1))),
+    };
+    let placement = if options.scrollback_friendly { Placement::Plain } else { placement };
+    return (placement, size);
+}
+
+// --at: one pixel of the image, or one cell averaged from the pixels it
+// covers at the size the image would be drawn (one pixel per cell)
+fn print_at(image: &Mat, at: (i32, i32), options: &Options) -> Result<(), Box<dyn std::error::Error>> {
+    if options.space == Space::Pixels {
+        return inspect::print_pixel(image, at, options.colors);
+    }
+    let (_, area) = frame_area(drawing_size(options)?, options);
+    let cells = scale_image(area, image_dims(image), height_rescale(options));
+    let mut resized = Mat::default();
+    imgproc::resize(image, &mut resized, core::Size::new(cells.0, cells.1), 0.0, 0.0, imgproc::INTER_AREA)?;
+    return inspect::print_pixel(&resized, at, options.colors);
+}
+
+// --dump-palette: the file palette, the adaptive palette picked for the
+// image at the size it would be drawn, or every color of the color mode
+fn print_active_palette(options: &Options) -> Result<(), Box<dyn std::error::Error>> {
+    let entries = if let Some(palette) = &options.palette {
+        palette.colors.iter().map(|&(r, g, b)| Color::Rgb(r, g, b)).collect()
+    } else if options.adaptive_palette {
+        let path = &options.image_path;
+        if path.is_empty() {
+            fail(EXIT_USAGE, "--dump-palette with --adaptive-palette needs an image to pick the colors for");
+        }
+        let image = load_image(path, options).unwrap_or_else(|e| fail(EXIT_BAD_IMAGE, e));
+        if image.empty() {
+            fail(EXIT_BAD_IMAGE, format!("Could not read the image: {}", path));
+        }
+        let drawn = drawn_pixels(options).ok_or("Error getting terminal size")?;
+        let (across, down) = options.density.cell_pixels();
+        let dims = scale_image(drawn, image_dims(&image), height_rescale(options) * down as f32 / across as f32);
+        // Every cell is snapped to the palette, so the cells are the palette
+        let mut colors: Vec<(u8, u8, u8)> = build_colormap(&image, dims, options)?
+            .into_iter()
+            .flatten()
+            .filter_map(|color| if let Color::Rgb(r, g, b) = color { Some((r, g, b)) } else { None })
+            .collect();
+        colors.sort();
+        colors.dedup();
+        colors.into_iter().map(|(r, g, b)| Color::Rgb(r, g, b)).collect()
+    } else {
+        match options.colors.entries() {
+            Some(entries) => entries,
+            None => {
+                println!("Truecolor draws every color as it is, there is no palette (see --adaptive-palette)");
+                return Ok(());
+            },
+        }
+    };
+    let width = get_terminal_size().map_or(80, |(width, _)| width);
+    inspect::print_color_table(&entries, (width / inspect::ENTRY_WIDTH).max(1) as usize);
+    return Ok(());
+}
+
+// A rendered image: the escape sequences that draw it from the cursor down,
+// ending each row with a newline (or, from render_into, moving to each row)
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+    pub text: String,
+    pub size: (i32, i32),  // Columns and rows of cells it takes up
+}
+
+// What render_sized hands the CLI along with the frame
+struct Rendered {
+    frame: Frame,
+    colormap: Vec<Vec<Color>>,  // The pixels the cells were made from, empty for --ascii and tiny frames
+    cell_pixels: (i32, i32),    // Pixels across and down in each cell
+    panels: bool,               // Whether the corner panels can go over the frame
+    detections: Option<usize>,  // How many things --detect-faces found
+}
+
+// Render an image to fit in `size` cells without printing anything, for
+// programs that put the frame somewhere of their own. The CLI prints these.
+// The terminal isn't asked anything, cells are shaped by cell_aspect (see
+// Options::probe_cells). Nothing is written anywhere but --verbose's notes
+// and timings on stderr: what only the CLI does (--save-png, --record,
+// --show-fps, --metadata-overlay, --profile, the lines under the image) is
+// left out.
+pub fn render_frame(image: &Mat, size: (i32, i32), options: &Options) -> Result<Frame, Box<dyn std::error::Error>> {
+    return render_sized(image, image_dims(image), size, Placement::Flow, options).map(|rendered| rendered.frame);
+}
+
+// Render an image to fit a w by h cell rectangle whose top left cell is at
+// column x, row y of the screen (from 0). Each row starts by moving the
+// cursor there and nothing outside the rectangle is touched, so the frame
+// can be written into one pane of a bigger layout from anywhere.
+pub fn render_into(image: &Mat, x: i32, y: i32, w: i32, h: i32, options: &Options) -> Result<Frame, Box<dyn std::error::Error>> {
+    return render_sized(image, image_dims(image), (w, h), Placement::At(x, y), options).map(|rendered| rendered.frame);
+}
+
+// Render every frame of an animation as it plays, at the clip's own pace.
+// Playback follows the loop and frame range options. Frames that fail to
+// render are skipped, the first error is returned at the end.
+pub fn render_animation<F>(path: &str, size: (i32, i32), options: &Options, on_frame: F) -> Result<(), Box<dyn std::error::Error>>
+where
+    F: FnMut(Frame),
+{
+    let mut player = animation::Player::new(path, options.playback(), options.frames());
+    return render_frames(&mut player, size, options, on_frame);
+}
+
+// Render every image a source yields until it runs dry, the same way. The
+// source failing ends it right away.
+pub fn render_frames<F>(source: &mut dyn FrameSource, size: (i32, i32), options: &Options, mut on_frame: F) -> Result<(), Box<dyn std::error::Error>>
+where
+    F: FnMut(Frame),
+{
+    let mut failed = None;
+    while let Some(image) = source.next_frame() {
+        match render_frame(&image?, size, options) {
+            Ok(frame) => on_frame(frame),
+            Err(e) => {
+                failed.get_or_insert(e);
+            },
+        }
+    }
+    return match failed {
+        Some(e) => Err(e),
+        None => Ok(()),
+    };
+}
+
+fn render_sized(
+    image: &Mat,
+    input_dims: (i32, i32),
+    (width, height): (i32, i32),
+    placement: Placement,
+    options: &Options,
+) -> Result<Rendered, Box<dyn std::error::Error>> {
+    // A tiny pane (a tmux split, say) can't show a picture, one cell of its
+    // average color at least says something about it
+    if too_small((width, height)) {
+        log_verbose(options, &format!(" {}x{} cells is too small for the image, drawing its average color", width, height));
+        let colormap = build_colormap(image, (1, 1), options)?;
+        let mut text = String::new();
+        write_bitmap(&mut text, &colormap, (1, 1), placement);
+        wrap_link(&mut text, 0, options.link.as_deref());
+        let frame = Frame { text, size: (1, 1) };
+        return Ok(Rendered { frame, colormap: Vec::new(), cell_pixels: (1, 1), panels: false, detections: None });
+    }
+
+    // Up to printing everything is measured in pixels of the resized image,
+    // the density says how many of them go in each cell
+    let density = if options.ascii { Density::One } else { options.density };
+    let (across, down) = density.cell_pixels();
+    let (width, height) = (width * across, height * down);
+    let height_scale = height_rescale(options) * down as f32 / across as f32;
+
+    // Recolor first, the annotations and detection see the swapped colors
+    let swapped;
+    let image = if options.map.is_empty() {
+        image
+    } else {
+        swapped = filters::swap_colors(image, &options.map, options.map_tolerance)?;
+        &swapped
+    };
+
+    // Annotations are placed in pixels of the image as it came in
+    let faces = if options.detect_faces { face_boxes(image, options)? } else { Vec::new() };
+    let detections = options.detect_faces.then_some(faces.len());
+    let annotated;
+    let image = if faces.is_empty() && options.rect.is_empty() && options.circle.is_empty() && options.text.is_empty() {
+        image
+    } else {
+        let annotations: Vec<_> = faces.iter().chain(&options.rect).chain(&options.circle).chain(&options.text).cloned().collect();
+        annotated = annotate::draw(image, &annotations)?;
+        &annotated
+    };
+
+    // Keyed out pixels go through the same blending as an alpha channel
+    let keyed;
+    let image = match options.chroma_key {
+        Some(key) => {
+            keyed = filters::chroma_key(image, key, options.tolerance)?;
+            &keyed
+        },
+        None => image,
+    };
+
+    // Fill the terminal with the busiest region instead of letterboxing
+    let cropped;
+    let (image, input_dims) = if options.smart_crop {
+        let aspect = height_scale * width as f32 / height as f32;
+        cropped = crop::smart_crop(image, aspect, options.luma())?;
+        (&cropped, image_dims(&cropped))
+    } else {
+        (image, input_dims)
+    };
+
+    // Calculate Scaling first
+    let mut new_dimensions: (i32, i32) = if options.tile {
+        tile_size((width, height), input_dims, height_scale)
+    } else if let Some(columns) = options.wrap {
+        // As wide as asked for and as tall as that makes it, fold() then
+        // stacks what doesn't fit across
+        scale_image((columns as i32 * across, i32::MAX / 2), input_dims, height_scale)
+    } else {
+        scale_image((width, height), input_dims, height_scale)
+    };
+    if let Some(max_cells) = options.max_cells {
+        let clamped = clamp_cells(new_dimensions, max_cells * (across * down) as i64);
+        if clamped != new_dimensions {
+            log_verbose(options, &format!(
+                " max-cells: {}x{} reduced to {}x{}",
+                new_dimensions.0, new_dimensions.1, clamped.0, clamped.1
+            ));
+        }
+        new_dimensions = clamped;
+    }
+
+    let mut text = String::new();
+    let started = Instant::now();
+    if options.ascii {
+        // Characters instead of colored cells, the cell options don't apply
+        let charset: Vec<char> = options.charset.as_deref().unwrap_or(ascii::DEFAULT_CHARSET).chars().collect();
+        let glyphs = ascii::build_glyphs(image, new_dimensions, &charset, options.ascii_equalize, options.luma())?;
+        ascii::write_glyphs(&mut text, &glyphs, placement);
+        wrap_link(&mut text, 0, options.link.as_deref());
+        let size = (glyphs.first().map_or(0, |row| row.len()) as i32, glyphs.len() as i32);
+        log_timing(options, "emit", started);
+        let frame = Frame { text, size };
+        return Ok(Rendered { frame, colormap: Vec::new(), cell_pixels: (1, 1), panels: true, detections });
+    }
+
+    // The backdrop fills the terminal, the part under the image shows
+    // through its transparent pixels
+    let (backdrop, under) = match &options.backdrop {
+        Some(backdrop) if !options.tile => {
+            let backdrop = fill_resize(backdrop, (width, height), height_scale)?;
+            let (left, top) = centered_origin((width, height), new_dimensions);
+            let rect = core::Rect::new(left, top, new_dimensions.0.min(width), new_dimensions.1.min(height));
+            let under = Mat::roi(&backdrop, rect)?.try_clone()?;
+            (Some(backdrop), Some(under))
+        },
+        _ => (None, None),
+    };
+
+    // Change the color map
+    let colormap = if options.fast {
+        // Work out every FAST_FACTOR-th cell and stretch it over its neighbors
+        let reduced = (
+            (new_dimensions.0 + FAST_FACTOR - 1) / FAST_FACTOR,
+            (new_dimensions.1 + FAST_FACTOR - 1) / FAST_FACTOR,
+        );
+        upscale_nearest(&build_colormap_over(image, reduced, options, under.as_ref())?, new_dimensions)
+    } else {
+        build_colormap_over(image, new_dimensions, options, under.as_ref())?
+    };
+    let (colormap, new_dimensions) = if options.tile {
+        let offset = (options.tile_offset.0 * across, options.tile_offset.1 * down);
+        (tile(&colormap, (width, height), offset, (options.repeat_x, options.repeat_y)), (width, height))
+    } else {
+        (colormap, new_dimensions)
+    };
+    // Paint the bars instead of leaving them blank
+    let (colormap, new_dimensions) = match (&backdrop, options.letterbox) {
+        (Some(backdrop), _) => (center_over(build_colormap(backdrop, (width, height), options)?, colormap), (width, height)),
+        (None, Some((r, g, b))) => (letterbox(colormap, (width, height), quantize(options, r, g, b)), (width, height)),
+        (None, None) => (colormap, new_dimensions),
+    };
+    let colormap = match options.grid {
+        Some(grid) => overlay_grid(colormap, grid, (across, down), options),
+        None => colormap,
+    };
+    let (colormap, new_dimensions) = if options.wrap.is_some() {
+        // A row of blank cells between the strips
+        let folded = fold(colormap, width as usize, down as usize);
+        let size = (folded.first().map_or(0, |row| row.len()) as i32, folded.len() as i32);
+        (folded, size)
+    } else {
+        (colormap, new_dimensions)
+    };
+    let started = Instant::now();
+    let size = if density == Density::One {
+        write_bitmap(&mut text, &colormap, new_dimensions, placement);
+        new_dimensions
+    } else {
+        let cells = density::group(&colormap, density);
+        density::write_cells(&mut text, &cells, placement);
+        (cells.first().map_or(0, |row| row.len()) as i32, cells.len() as i32)
+    };
+    wrap_link(&mut text, 0, options.link.as_deref());
+    log_timing(options, "emit", started);
+    let frame = Frame { text, size };
+    return Ok(Rendered { frame, colormap, cell_pixels: (across, down), panels: true, detections });
+}
+
+// --cascade, or the frontal face cascade OpenCV installed
+fn cascade_path(options: &Options) -> Result<String, String> {
+    if let Some(path) = &options.cascade {
+        return Ok(path.clone());
+    }
+    return faces::default_cascade()
+        .map(|path| path.to_string_lossy().into_owned())
+        .ok_or_else(|| format!("Could not find OpenCV's {}, give its path with --cascade", faces::DEFAULT_CASCADE));
+}
+
+// A box around everything the cascade finds, drawn along with --rect
+fn face_boxes(image: &Mat, options: &Options) -> Result<Vec<annotate::Annotation>, Box<dyn std::error::Error>> {
+    let classifier = options.classifier.as_ref().ok_or("--detect-faces needs the cascade, see Options::load_cascade")?;
+    let found = faces::detect(&mut classifier.lock().unwrap(), image)?;
+    log_verbose(options, &format!(" Found {} with the cascade", found.len()));
+    return Ok(found.into_iter().map(|r| (annotate::Shape::Rect(r.x, r.y, r.width, r.height), DEFAULT_FACE_COLOR)).collect());
+}
+
+// The --metadata-overlay lines of the image being shown, read again
+// whenever the image changes
+static SHOOTING_INFO: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+#[cfg(feature = "exif")]
+fn read_shooting_info(path: &str, options: &Options) {
+    if !options.metadata_overlay {
+        return;
+    }
+    let lines = metadata::shooting_info(path).unwrap_or_else(|e| {
+        log_verbose(options, &format!("No shooting info to show: {}", e));
+        Vec::new()
+    });
+    *SHOOTING_INFO.lock().unwrap() = lines;
+}
+
+// run_render turns --metadata-overlay down before this could matter
+#[cfg(not(feature = "exif"))]
+fn read_shooting_info(_path: &str, _options: &Options) {}
+
+fn write_shooting_info<U: Fn(i32, i32) -> Color>(out: &mut String, size: (i32, i32), placement: Placement, under: U, options: &Options) {
+    if !options.metadata_overlay {
+        return;
+    }
+    let lines = SHOOTING_INFO.lock().unwrap();
+    metadata::write_panel(out, &lines, options.overlay_corner, size, placement, under, |r, g, b| panel_color(options, r, g, b));
+}
+
+// Colors of the corner panels, exact when the frame picked its own palette
+fn panel_color(options: &Options, r: u8, g: u8, b: u8) -> Color {
+    if options.adaptive_palette && options.palette.is_none() {
+        return Color::Rgb(r, g, b);
+    }
+    return quantize(options, r, g, b);
+}
+
+// The --show-fps clock: when the last frame was drawn and the smoothed
+// seconds between frames, once there have been two
+static FRAME_CLOCK: Mutex<Option<(Instant, Option<f64>)>> = Mutex::new(None);
+
+// The --show-fps panel, frames per second since the last frame and how long
+// this one took to render (not counting printing it)
+fn write_fps<U: Fn(i32, i32) -> Color>(out: &mut String, size: (i32, i32), placement: Placement, under: U, started: Instant, options: &Options) {
+    // A line under every frame would only push the frames apart
+    if !options.show_fps || placement == Placement::Plain {
+        return;
+    }
+    let now = Instant::now();
+    let mut clock = FRAME_CLOCK.lock().unwrap();
+    let interval = clock.map(|(last, average)| {
+        let delta = now.duration_since(last).as_secs_f64();
+        return average.map_or(delta, |average| average + (delta - average) * FPS_SMOOTHING);
+    });
+    *clock = Some((now, interval));
+    // Out of the way of the shooting info
+    let corner = if options.metadata_overlay && options.overlay_corner == metadata::Corner::TopRight {
+        metadata::Corner::TopLeft
+    } else {
+        metadata::Corner::TopRight
+    };
+    let lines = [fps_line(interval, now.duration_since(started))];
+    metadata::write_panel(out, &lines, corner, size, placement, under, |r, g, b| panel_color(options, r, g, b));
+}
+
+fn fps_line(interval: Option<f64>, render: Duration) -> String {
+    let ms = format!("{:.1} ms", render.as_secs_f64() * 1000.0);
+    return match interval {
+        Some(seconds) if seconds > 0.0 => format!("{:.1} fps  {}", 1.0 / seconds, ms),
+        _ => ms,
+    };
+}
+
+// The --record encoder, shared by everything that draws
+static RECORDER: Mutex<Option<export::Recorder>> = Mutex::new(None);
+
+fn record_frame(colormap: &[Vec<Color>], cell_pixels: (i32, i32), options: &Options) {
+    let mut recorder = RECORDER.lock().unwrap();
+    let recorder = match recorder.as_mut() {
+        Some(recorder) => recorder,
+        None => return,
+    };
+    let cell_size = options.cell_size.unwrap_or(export::DEFAULT_CELL_SIZE);
+    match recorder.add(colormap, cell_size, cell_pixels) {
+        Ok(false) if recorder.dropped == 1 => {
+            eprintln!("Recorded {} frames, the rest are left out", recorder.frames);
+        },
+        Ok(_) => {},
+        Err(e) => eprintln!("Error recording a frame: {}", e),
+    }
+}
+
+// Close the --record file, if there is one
+fn stop_recording() {
+    if let Some(recorder) = RECORDER.lock().unwrap().take() {
+        if let Err(e) = recorder.finish() {
+            eprintln!("Error finishing the recording: {}", e);
+        }
+    }
+}
+
+// Size in cells of the last frame drawn full screen
+static LAST_FRAME: Mutex<Option<(i32, i32)>> = Mutex::new(None);
+
+// Whether drawing a frame of this size over the last one leaves some of it showing
+fn uncovers_last(last: Option<(i32, i32)>, size: (i32, i32)) -> bool {
+    return last.is_some_and(|(width, height)| size.0 < width || size.1 < height);
+}
+
+// Wipe the screen before a frame that's smaller than the last one, past its
+// edges the old rows would otherwise stay up
+fn clear_if_shrunk(options: &Options, size: (i32, i32)) {
+    if !options.fullscreen || options.scrollback_friendly {
+        return;
+    }
+    let mut last = LAST_FRAME.lock().unwrap();
+    if uncovers_last(*last, size) {
+        print!("\x1B[2J\x1B[H");
+    }
+    *last = Some(size);
+}
+
+// Set by --scroll-region once the rows under the image are the only ones that scroll
+static SCROLL_REGION_SET: AtomicBool = AtomicBool::new(false);
+
+// How many lines go under the image: what finish_frame prints, and the
+// shooting info when it can't be drawn over the frame
+fn status_lines(options: &Options) -> i32 {
+    let below = if options.metadata_overlay && options.scrollback_friendly { SHOOTING_INFO.lock().unwrap().len() as i32 } else { 0 };
+    if options.quiet {
+        return below;
+    }
+    return below + options.watching() as i32 + options.detect_faces as i32;
+}
+
+// Everything printed under the image, which took up `rows` rows
+fn finish_frame(options: &Options, rows: i32, detections: Option<usize>) -> Result<(), Box<dyn std::error::Error>> {
+    if options.scroll_region {
+        // Anything printed later (the hint, errors, the prompt) scrolls
+        // below the image instead of pushing it off the top
+        let (_, height) = get_terminal_size()?;
+        if rows > 0 && rows + 2 <= height {
+            print!("\x1B[{};{}r\x1B[{};1H", rows + 1, height, rows + 1);
+            SCROLL_REGION_SET.store(true, Ordering::SeqCst);
+        }
+    }
+    if let Some(found) = detections.filter(|_| !options.quiet) {
+        // Other cascades find other things
+        let noun = match (options.cascade.is_some(), found == 1) {
+            (false, true) => "face",
+            (false, false) => "faces",
+            (true, true) => "match",
+            (true, false) => "matches",
+        };
+        println!("{} {} found", found, noun);
+    }
+    if options.watching() && !options.quiet {
+        println!("Press Ctrl-C to Exit");
+    }
+    if options.home_only {
+        // Nothing was cleared, drop anything left below from a taller frame
+        print!("\x1B[J");
+        io::stdout().flush()?;
+    }
+    return Ok(());
+}
+
+// Which way --strip collapses the image
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub enum Strip {
+    #[value(name = "rows")]
+    Row,     // One cell per column of the image
+    #[value(name = "cols")]
+    Column,  // One cell per row of the image
+}
+
+// What --at counts in
+#[derive(Clone, Copy, Debug, Default, PartialEq, clap::ValueEnum)]
+pub enum Space {
+    #[default]
+    Pixels,  // Pixels of the image itself
+    Cells,   // Cells of the image as it would be drawn, from its top left
+}
+
+// Options for `imprev render`
+#[derive(clap::Args, Clone, Debug, Default)]
+pub struct Options {
+    /// Image, animation, FIFO or (with --strip) directory to show
+    #[arg(default_value = "", hide_default_value = true, required_unless_present_any = ["watch_dir", "dump_palette"])]
+    pub image_path: String,
+    /// Show the newest image in this directory, switching whenever a newer one appears
+    #[arg(long, value_name = "DIR", conflicts_with = "image_path")]
+    pub watch_dir: Option<String>,
+    /// Loop the animation forever
+    #[arg(short = 'l', long = "loop", group = "playback")]
+    pub play_loop: bool,
+    /// Play once and keep the last frame on screen
+    #[arg(long, group = "playback")]
+    pub play_once: bool,
+    /// Play once and exit
+    #[arg(long, group = "playback")]
+    pub play_exit: bool,
+    /// Keep the last frame of an animation up this many seconds, then exit (implies --play-once)
+    #[arg(long, value_name = "SECS", value_parser = cli::parse_seconds, conflicts_with = "play_loop")]
+    pub hold: Option<Duration>,
+    /// Only show every Nth frame of an animation
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    pub frame_step: u32,
+    /// Only play frames start (inclusive) to end (exclusive) of an animation, eg 10:50
+    #[arg(long, value_name = "START:END", value_parser = animation::parse_range)]
+    pub frame_range: Option<(u32, Option<u32>)>,
+    /// Don't redraw frames of an animation that look the same as the last one drawn
+    #[arg(long)]
+    pub loop_detect: bool,
+    /// How different a frame has to be to get drawn with --loop-detect, the average change per channel (0-255)
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_STILL_THRESHOLD, requires = "loop_detect")]
+    pub still_threshold: f64,
+    /// Skip the delays of the frames --loop-detect doesn't draw as well, speeding through still stretches
+    #[arg(long, requires = "loop_detect")]
+    pub collapse_still: bool,
+    /// Snap to 256 colors picked for this image (median cut), drawn in truecolor
+    #[arg(long)]
+    pub adaptive_palette: bool,
+    /// Only pick this many colors for the adaptive palette, 2 to 256 (implies --adaptive-palette)
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(2..=256))]
+    pub max_colors: Option<u32>,
+    /// Snap every pixel to the nearest color in a file of hex colors
+    #[arg(long = "palette-file", value_name = "PATH", value_parser = Palette::load)]
+    pub palette: Option<Palette>,
+    /// Also write the rendering to an image, one rectangle per cell
+    #[arg(long, value_name = "FILE")]
+    pub save_png: Option<String>,
+    /// Record every frame drawn into an animated GIF, until Ctrl-C or 1000 frames
+    #[arg(long, value_name = "FILE.gif")]
+    pub record: Option<String>,
+    /// Pixel size of each cell in the saved image [default: 8x16]
+    #[arg(long, value_name = "WxH", value_parser = cli::parse_cell_size)]
+    pub cell_size: Option<(i32, i32)>,
+    /// Draw into this rectangle of the terminal instead of from the top left, eg 40,0,40x20
+    #[arg(long, value_name = "X,Y,WxH", value_parser = cli::parse_rect)]
+    pub contain_within: Option<(i32, i32, i32, i32)>,
+    /// Write each frame to stdout all at once (frame) or row by row (line)
+    #[arg(long, value_enum, default_value = "frame")]
+    pub buffering: Buffering,
+    /// Print stage timings to stderr
+    #[arg(short, long)]
+    pub verbose: bool,
+    /// Don't print the exit hint under the image
+    #[arg(short, long)]
+    pub quiet: bool,
+    /// Color depth: 8, 16, 256, truecolor or auto (terminfo and COLORTERM)
+    #[arg(long, default_value = "auto", value_parser = cli::parse_colors)]
+    pub colors: ColorMode,
+    /// Clear the screen and fill it, redrawing on resize and changes until Ctrl-C
+    #[arg(long)]
+    pub fullscreen: bool,
+    /// Print the image where the cursor is, like any other output (the default for still images)
+    #[arg(long, conflicts_with = "fullscreen")]
+    pub preview_at_cursor: bool,
+    /// Exit after drawing instead of watching for resizes and changes (the default when stdout isn't a terminal)
+    #[arg(long)]
+    pub once: bool,
+    /// Keep watching even when stdout doesn't look like a terminal, for wrappers that hide it
+    #[arg(long, visible_alias = "interactive", conflicts_with = "once")]
+    pub force_tty: bool,
+    /// Hide the banding from snapping to fewer colors: none, floyd (smoothest) or bayer (steady in animations)
+    #[arg(long, value_enum, default_value = "none")]
+    pub dither_type: dither::Dither,
+    /// Set the window or tab title while the image is up, to the file name unless given
+    #[arg(long, value_name = "TEXT", num_args = 0..=1, require_equals = true, conflicts_with = "scrollback_friendly")]
+    pub title: Option<Option<String>>,
+    /// Don't install signal handlers, watch for resizes by checking the size every half second
+    #[arg(long)]
+    pub no_signals: bool,
+    /// Assume cells are twice as tall as wide instead of working their shape out from the terminal's size in pixels
+    #[arg(long)]
+    pub no_cell_probe: bool,
+    /// Width over height of a cell, from probe_cells. None draws as if cells were twice as tall as wide.
+    #[arg(skip)]
+    pub cell_aspect: Option<f32>,
+    /// Draw the camera, lens, exposure and date from the photo's EXIF in a corner of the frame
+    #[arg(long)]
+    pub metadata_overlay: bool,
+    /// Which corner --metadata-overlay draws in
+    #[arg(long, value_enum, default_value = "bottom-left", requires = "metadata_overlay")]
+    pub overlay_corner: metadata::Corner,
+    /// Draw the frames per second achieved and the milliseconds each frame takes to render in a corner, for animations, streams and watching (not with --scrollback-friendly)
+    #[arg(long)]
+    pub show_fps: bool,
+    /// Keep the size the image was first drawn at, ignoring resizes
+    #[arg(long, visible_alias = "orientation-lock")]
+    pub lock_size: bool,
+    /// Only print colors and newlines, no cursor movement or clearing, so the output can be logged or pasted (the default when stdout isn't a terminal)
+    #[arg(long, visible_alias = "plain", conflicts_with_all = ["contain_within", "home_only", "scroll_region", "clear_on_exit"])]
+    pub scrollback_friendly: bool,
+    /// Decode huge images at 1/2, 1/4 or 1/8 size, picked to still cover the terminal
+    #[arg(long)]
+    pub sample: bool,
+    /// Decode binary PPM and PGM files a band of rows at a time, shrinking as they go, and other formats like --sample
+    #[arg(long)]
+    pub low_memory: bool,
+    /// Pick the nearest colors by CIE Lab distance instead of RGB, slower but closer to the eye
+    #[arg(long)]
+    pub perceptual: bool,
+    /// Draw on the alternate screen, so quitting brings back what was there before
+    #[arg(long, overrides_with = "no_clear_on_exit")]
+    pub clear_on_exit: bool,
+    /// Leave the image on screen and in the scrollback after quitting (the default)
+    #[arg(long, overrides_with = "clear_on_exit")]
+    pub no_clear_on_exit: bool,
+    /// Redraw over the last frame from the top left instead of clearing the screen
+    #[arg(long)]
+    pub home_only: bool,
+    /// Only let the rows under the image scroll, so later output can't push it away
+    #[arg(long)]
+    pub scroll_region: bool,
+    /// Re-read and redraw the file every this many seconds
+    #[arg(long, value_name = "SECS", value_parser = cli::parse_seconds)]
+    pub refresh: Option<Duration>,
+    /// Fill the terminal, cropping to the most detailed region
+    #[arg(long)]
+    pub smart_crop: bool,
+    /// How images are delimited when reading a FIFO: length or lines
+    #[arg(long, default_value = "length", value_parser = stream::Framing::parse)]
+    pub framing: stream::Framing,
+    /// Draw faint lines every this many cells, or "thirds" for the rule of thirds
+    #[arg(long, value_name = "N|thirds", value_parser = Grid::parse)]
+    pub grid: Option<Grid>,
+    /// Color of the --grid lines [default: #ffffff]
+    #[arg(long, value_name = "HEX", value_parser = palette::parse_hex, requires = "grid")]
+    pub grid_color: Option<(u8, u8, u8)>,
+    /// Center the image and fill the bars with this hex color
+    #[arg(long = "letterbox-color", value_name = "HEX", value_parser = palette::parse_hex)]
+    pub letterbox: Option<(u8, u8, u8)>,
+    /// Pixels per cell: 1, 2 (half blocks), 4 (quadrants) or 8 (braille)
+    #[arg(long, value_enum, default_value = "1")]
+    pub density: Density,
+    /// Draw with characters by brightness instead of colored cells
+    #[arg(long)]
+    pub ascii: bool,
+    /// Characters for --ascii, darkest first (implies --ascii)
+    #[arg(long, value_name = "CHARS", value_parser = clap::builder::NonEmptyStringValueParser::new())]
+    pub charset: Option<String>,
+    /// Red, green and blue shares of the brightness [default: 0.299,0.587,0.114]
+    #[arg(long, value_name = "R,G,B", value_parser = cli::parse_weights)]
+    pub luma_weights: Option<(f32, f32, f32)>,
+    /// Equalize the brightness before picking characters, for photos in --ascii
+    #[arg(long)]
+    pub ascii_equalize: bool,
+    /// Color every other cell and repeat it, for slow machines
+    #[arg(long)]
+    pub fast: bool,
+    /// Treat pixels close to this hex color as transparent, eg the white behind a logo
+    #[arg(long, value_name = "HEX", value_parser = palette::parse_hex)]
+    pub chroma_key: Option<(u8, u8, u8)>,
+    /// How far each channel can be from the --chroma-key color and still count
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_TOLERANCE)]
+    pub tolerance: u8,
+    /// Repaint pixels close to each FROM color in its TO color before drawing, in the order given
+    #[arg(long, value_name = "FROM=TO,...", value_delimiter = ',', value_parser = cli::parse_color_swap)]
+    pub map: Vec<cli::ColorPair>,
+    /// How far each channel can be from a --map FROM color and still be repainted
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_MAP_TOLERANCE)]
+    pub map_tolerance: u8,
+    /// Show transparent parts of the image over a checkerboard instead of the terminal's background
+    #[arg(long, conflicts_with = "background_image")]
+    pub checkerboard: bool,
+    /// Width of the checkerboard squares in cells (implies --checkerboard) [default: 2]
+    #[arg(long, value_name = "CELLS", value_parser = clap::value_parser!(u32).range(1..), conflicts_with = "background_image")]
+    pub checker_size: Option<u32>,
+    /// The two colors of the checkerboard (implies --checkerboard) [default: #999999,#666666]
+    #[arg(long, value_name = "A,B", value_parser = cli::parse_color_pair, conflicts_with = "background_image")]
+    pub checker_colors: Option<cli::ColorPair>,
+    /// Fill the terminal with this image and draw the (transparent) image over it
+    #[arg(long, value_name = "PATH")]
+    pub background_image: Option<String>,
+    /// The loaded --background-image, in BGR
+    #[arg(skip)]
+    pub backdrop: Option<Mat>,
+    /// Draw the image this many cells wide, stacking the parts past the terminal's width below
+    #[arg(long, value_name = "COLUMNS", value_parser = clap::value_parser!(u32).range(1..),
+          conflicts_with_all = ["tile", "letterbox", "background_image", "smart_crop", "contain_within"])]
+    pub wrap: Option<u32>,
+    /// Never render more than this many cells in total
+    #[arg(long, value_parser = clap::value_parser!(i64).range(1..))]
+    pub max_cells: Option<i64>,
+    /// Paint the in focus (sharpest) parts of the image in a bright color over the normal render
+    #[arg(long)]
+    pub focus_peaking: bool,
+    /// How big the change in brightness around a pixel has to be for --focus-peaking to count it as sharp, 0 to 255
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_PEAKING_THRESHOLD, requires = "focus_peaking")]
+    pub peaking_threshold: u8,
+    /// Color of the --focus-peaking highlight [default: #ff0000]
+    #[arg(long, value_name = "HEX", value_parser = palette::parse_hex, requires = "focus_peaking")]
+    pub peaking_color: Option<(u8, u8, u8)>,
+    /// Draw a rectangle on the image, in pixels of the image, eg 10,10,200,100:#00ff00 (repeatable)
+    #[arg(long, value_name = "X,Y,W,H[:HEX]", value_parser = annotate::parse_rect)]
+    pub rect: Vec<annotate::Annotation>,
+    /// Draw a circle on the image, in pixels of the image (repeatable)
+    #[arg(long, value_name = "X,Y,R[:HEX]", value_parser = annotate::parse_circle)]
+    pub circle: Vec<annotate::Annotation>,
+    /// Write text on the image, X,Y is the bottom left of the text (repeatable)
+    #[arg(long, value_name = "TEXT@X,Y[:HEX]", value_parser = annotate::parse_text)]
+    pub text: Vec<annotate::Annotation>,
+    /// Box the faces OpenCV's frontal face cascade finds in the image, and say how many there are
+    #[arg(long)]
+    pub detect_faces: bool,
+    /// Look for things with this Haar cascade XML file instead of the frontal face one (implies --detect-faces)
+    #[arg(long, value_name = "PATH")]
+    pub cascade: Option<String>,
+    /// The loaded --detect-faces cascade, shared by every copy of the options
+    #[arg(skip)]
+    pub classifier: Option<Arc<Mutex<opencv::objdetect::CascadeClassifier>>>,
+    /// Make the image a hyperlink to this URL in terminals that support them (OSC 8)
+    #[arg(long, value_name = "URL", value_parser = cli::parse_link)]
+    pub link: Option<String>,
+    /// Mosaic the image into squares this many pixels across before drawing it, to hide detail
+    #[arg(long, value_name = "BLOCK", value_parser = clap::value_parser!(u32).range(2..))]
+    pub pixelate: Option<u32>,
+    /// Simulate a color vision deficiency: protanopia, deuteranopia or tritanopia
+    #[arg(long, value_parser = filters::Cvd::parse)]
+    pub cvd: Option<filters::Cvd>,
+    /// Print the average and most common color instead of the image and exit
+    #[arg(long)]
+    pub measure: bool,
+    /// Print the colors the image would be drawn with (256, 16 or 8 color, adaptive or file palette) and exit
+    #[arg(long, conflicts_with = "watch_dir")]
+    pub dump_palette: bool,
+    /// Print the color at X,Y (see --space) instead of the image and exit
+    #[arg(long, value_name = "X,Y", value_parser = cli::parse_offset)]
+    pub at: Option<(i32, i32)>,
+    /// What --at counts in: pixels of the image or cells of the image as drawn
+    #[arg(long, value_enum, default_value = "pixels")]
+    pub space: Space,
+    /// Render this many times without drawing and print timing stats per stage
+    #[arg(long, value_name = "RUNS", value_parser = clap::value_parser!(u32).range(1..))]
+    pub profile: Option<u32>,
+    /// Print averaged strips instead of the image and exit
+    #[arg(long, value_enum)]
+    pub strip: Option<Strip>,
+    /// Order of the --strip strips when given a directory: name, mtime (newest first), size (largest first) or random
+    #[arg(long, value_enum, default_value_t = SortBy::Name)]
+    pub sort: SortBy,
+    /// Put a directory's --strip strips in the opposite --sort order
+    #[arg(long)]
+    pub reverse: bool,
+    /// Repeat the image across the terminal at one pixel per cell
+    #[arg(long)]
+    pub tile: bool,
+    /// Shift the tiling by this many cells (implies --tile)
+    #[arg(long, value_name = "X,Y", default_value = "0,0", value_parser = cli::parse_offset, allow_hyphen_values = true)]
+    pub tile_offset: (i32, i32),
+    /// Only repeat the tile this many times across (implies --tile)
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub repeat_x: Option<u32>,
+    /// Only repeat the tile this many times down (implies --tile)
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub repeat_y: Option<u32>,
+}
+
+impl Options {
+    // --luma-weights, for everything that works on brightness alone
+    fn luma(&self) -> (f32, f32, f32) {
+        return self.luma_weights.unwrap_or(filters::REC_601);
+    }
+
+    // Load the --detect-faces cascade (--cascade or OpenCV's frontal face
+    // one). Rendering with detect_faces set fails until this is done.
+    pub fn load_cascade(&mut self) -> Result<(), String> {
+        self.classifier = Some(Arc::new(Mutex::new(faces::load(&cascade_path(self)?)?)));
+        return Ok(());
+    }
+
+    // Work the cells' shape out from the terminal's size in pixels, unless
+    // --no-cell-probe. Rendering never asks the terminal itself.
+    pub fn probe_cells(&mut self) {
+        if self.no_cell_probe {
+            return;
+        }
+        self.cell_aspect = match get_terminal_geometry() {
+            Ok((cells, Some(pixels))) => cell_aspect(cells, pixels),
+            _ => None,
+        };
+    }
+
+    // None = honor the GIF loop count
+    fn playback(&self) -> Option<Playback> {
+        if self.play_loop {
+            return Some(Playback::Loop);
+        }
+        if self.play_once {
+            return Some(Playback::Once);
+        }
+        if self.play_exit {
+            return Some(Playback::Exit);
+        }
+        return None;
+    }
+
+    fn metric(&self) -> Metric {
+        return if self.perceptual { Metric::Lab } else { Metric::Rgb };
+    }
+
+    // Whether the image stays up and gets redrawn, rather than printed and left behind
+    fn watching(&self) -> bool {
+        return self.fullscreen && !self.once;
+    }
+
+    fn frames(&self) -> animation::Frames {
+        let (start, end) = self.frame_range.unwrap_or((0, None));
+        let skip_still = self.loop_detect.then_some(self.still_threshold);
+        return animation::Frames { step: self.frame_step.max(1), start, end, skip_still, collapse_still: self.collapse_still };
+    }
+}
+
+// Read a still image from disk, keeping its alpha channel
+fn load_image(path: &str, options: &Options) -> Result<Mat, opencv::Error> {
+    let started = Instant::now();
+    let banded = match drawn_pixels(options) {
+        Some(drawn) if options.low_memory => bands::load_reduced(path, |image| banded_size(image, drawn))?,
+        _ => None,
+    };
+    let image = match banded {
+        Some(image) => image,
+        None => {
+            let flags = if options.sample || options.low_memory { sample_flags(path, options) } else { imgcodecs::IMREAD_UNCHANGED };
+            imgcodecs::imread(path, flags)?
+        },
+    };
+    let image = normalize_image(image)?;
+    #[cfg(feature = "icc")]
+    let image = match std::fs::File::open(path) {
+        Ok(file) => convert_profile(&mut io::BufReader::new(file), image, options),
+        Err(_) => image,
+    };
+    log_timing(options, "decode", started);
+    return Ok(image);
+}
+
+// --sample: have the decoder shrink the image by 2, 4 or 8 as it goes (JPEG
+// skips most of the work), as far as it stays well over the pixels drawn
+fn sample_flags(path: &str, options: &Options) -> i32 {
+    let (image, drawn) = match (header::read_dimensions(path), drawn_pixels(options)) {
+        (Some(image), Some(drawn)) => (image, drawn),
+        _ => return imgcodecs::IMREAD_UNCHANGED,
+    };
+    let factor = sample_reduction(image, drawn);
+    if factor > 1 {
+        log_verbose(options, &format!(" sample: decoding {}x{} at 1/{}", image.0, image.1, factor));
+    }
+    match factor {
+        8 => imgcodecs::IMREAD_REDUCED_COLOR_8,
+        4 => imgcodecs::IMREAD_REDUCED_COLOR_4,
+        2 => imgcodecs::IMREAD_REDUCED_COLOR_2,
+        _ => imgcodecs::IMREAD_UNCHANGED,
+    }
+}
+
+// Pixels of the resized image the frame would be made of at most, None
+// without a terminal to measure
+fn drawn_pixels(options: &Options) -> Option<(i32, i32)> {
+    let (across, down) = options.density.cell_pixels();
+    let (columns, rows) = frame_area(drawing_size(options).ok()?, options).1;
+    return Some((columns * across, rows * down));
+}
+
+// --low-memory: the size a banded decode shrinks to, the same margin over
+// the pixels drawn as --sample but any factor, never bigger than the image
+fn banded_size((width, height): (i32, i32), (across, down): (i32, i32)) -> (i32, i32) {
+    let needed = (across * SAMPLE_OVERSAMPLING, (down as f32 / DEFAULT_HEIGHT_RESCALE) as i32 * SAMPLE_OVERSAMPLING);
+    let scale = (needed.0 as f64 / width as f64).max(needed.1 as f64 / height as f64).min(1.0);
+    return (((width as f64 * scale).round() as i32).max(1), ((height as f64 * scale).round() as i32).max(1));
+}
+
+// The biggest factor that leaves the image at least SAMPLE_OVERSAMPLING times
+// the pixels drawn on both sides (cells are twice as tall as wide), 1 for none
+fn sample_reduction((width, height): (i32, i32), (across, down): (i32, i32)) -> i32 {
+    let needed = (across * SAMPLE_OVERSAMPLING, (down as f32 / DEFAULT_HEIGHT_RESCALE) as i32 * SAMPLE_OVERSAMPLING);
+    return [8, 4, 2].into_iter().find(|factor| width / factor >= needed.0 && height / factor >= needed.1).unwrap_or(1);
+}
+
+// IMREAD_UNCHANGED can give us any depth and channel count, bring it to
+// 8 bit BGR (or BGRA when there's alpha) which is what build_colormap reads
+fn normalize_image(image: Mat) -> Result<Mat, opencv::Error> {
+    if image.empty() {
+        return Ok(image);
+    }
+    let mut image = image;
+    if image.depth() == core::CV_64F {
+        // Color conversion and tone mapping only take 32 bit floats
+        let mut converted = Mat::default();
+        image.convert_to(&mut converted, core::CV_32F, 1.0, 0.0)?;
+        image = converted;
+    }
+    if image.channels() == 1 {
+        let mut converted = Mat::default();
+        imgproc::cvt_color(&image, &mut converted, imgproc::COLOR_GRAY2BGR, 0)?;
+        image = converted;
+    }
+    if image.depth() == core::CV_32F {
+        if let Some(tone_mapped) = filters::tone_map(&image)? {
+            return Ok(tone_mapped);
+        }
+    }
+    if image.depth() != core::CV_8U {
+        let scale = match image.depth() {
+            core::CV_16U => 1.0 / 257.0,
+            core::CV_32F => 255.0,
+            _ => 1.0,
+        };
+        let mut converted = Mat::default();
+        image.convert_to(&mut converted, core::CV_8U, scale, 0.0)?;
+        image = converted;
+    }
+    return Ok(image);
+}
+
+// Decode an encoded image held in memory, eg a frame from a FIFO
+fn decode_image(bytes: &[u8], options: &Options) -> Result<Mat, opencv::Error> {
+    let started = Instant::now();
+    let image = imgcodecs::imdecode(&core::Vector::<u8>::from_slice(bytes), imgcodecs::IMREAD_UNCHANGED)?;
+    let image = normalize_image(image)?;
+    #[cfg(feature = "icc")]
+    let image = convert_profile(&mut io::Cursor::new(bytes), image, options);
+    log_timing(options, "decode", started);
+    return Ok(image);
+}
+
+// Bring the pixels into sRGB using the profile embedded in the file. A broken
+// profile isn't worth failing over, the pixels are drawn as they are.
+#[cfg(feature = "icc")]
+fn convert_profile(file: &mut (impl io::Read + io::Seek), mut image: Mat, options: &Options) -> Mat {
+    if image.empty() {
+        return image;
+    }
+    if let Err(e) = icc::convert_to_srgb(file, &mut image) {
+        log_verbose(options, &format!("Ignoring the embedded color profile: {}", e));
+    }
+    return image;
+}
+
+// Extensions we treat as still images when scanning a directory
+const IMAGE_EXTENSIONS: [&str; 9] = ["png", "jpg", "jpeg", "bmp", "webp", "tif", "tiff", "ppm", "pgm"];
+
+// Still images in a directory, in no particular order
+fn list_images(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut images = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_image = path.extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| IMAGE_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()));
+        if is_image && path.is_file() {
+            images.push(path);
+        }
+    }
+    return Ok(images);
+}
+
+// What --sort puts the images of a directory in order by
+#[derive(Clone, Copy, Debug, Default, PartialEq, clap::ValueEnum)]
+pub enum SortBy {
+    #[default]
+    Name,
+    Mtime,   // Newest first, like ls -t
+    Size,    // Largest first, like ls -S
+    Random,  // A new order every run
+}
+
+// Images from list_images in --sort order. Ties, and files that can't be
+// looked at, go by name.
+fn sort_images(mut images: Vec<PathBuf>, sort: SortBy, reverse: bool) -> Vec<PathBuf> {
+    images.sort();
+    match sort {
+        SortBy::Name => {},
+        SortBy::Mtime => images.sort_by_cached_key(|path| std::cmp::Reverse(path.metadata().and_then(|m| m.modified()).ok())),
+        SortBy::Size => images.sort_by_cached_key(|path| std::cmp::Reverse(path.metadata().map(|m| m.len()).ok())),
+        SortBy::Random => {
+            use std::hash::BuildHasher;
+            // Hashers are seeded randomly for each process
+            let state = std::collections::hash_map::RandomState::new();
+            images.sort_by_cached_key(|path| state.hash_one(path));
+        },
+    }
+    if reverse {
+        images.reverse();
+    }
+    return images;
+}
+
+// Collapse each image to a single averaged row (or column) of cells, like a
+// movie barcode, and print one strip per image
+fn print_strips(options: &Options, strip: Strip) -> Result<(), Box<dyn std::error::Error>> {
+    let target = Path::new(&options.image_path);
+    let paths = if target.is_dir() { sort_images(list_images(target)?, options.sort, options.reverse) } else { vec![target.to_path_buf()] };
+    let (width, height) = get_terminal_size().unwrap_or(PIPED_SIZE);
+    let dimensions = match strip {
+        Strip::Row => (width, 1),
+        Strip::Column => (1, (height - 1).max(1)),
+    };
+
+    let mut strips = Vec::new();
+    for path in &paths {
+        let path = path.to_string_lossy();
+        let image = load_image(&path, options)?;
+        if image.empty() {
+            eprintln!("Could not read the image: {}", path);
+            continue;
+        }
+        let colormap = build_colormap(&image, dimensions, options)?;
+        strips.push(colormap.into_iter().flatten().collect::<Vec<Color>>());
+    }
+
+    // Row strips stack downwards, column strips sit side by side
+    let colormap = match strip {
+        Strip::Row => strips,
+        Strip::Column => (0..dimensions.1 as usize)
+            .map(|r| strips.iter().map(|column| column[r]).collect())
+            .collect(),
+    };
+    let rows = colormap.len() as i32;
+    let cols = colormap.first().map_or(0, |row| row.len()) as i32;
+    print_bitmap(colormap, (cols, rows), options.link.as_deref());
+    return Ok(());
+}
+
+// Run decoding and rendering over and over, timing every stage. Frames
+// are thrown away, how long printing takes depends on the terminal.
+fn print_profile(options: &Options, runs: u32) -> Result<(), Box<dyn std::error::Error>> {
+    let size = get_terminal_size().unwrap_or(PIPED_SIZE);
+    let mut bytes = 0;
+    profile::start();
+    for _ in 0..runs {
+        let image = load_image(&options.image_path, options).unwrap_or_else(|e| fail(EXIT_BAD_IMAGE, e));
+        if image.empty() {
+            fail(EXIT_BAD_IMAGE, format!("Could not read the image: {}", options.image_path));
+        }
+        bytes += render_frame(&image, size, options)?.text.len();
+    }
+    profile::print_table(&profile::finish(), runs, bytes);
+    return Ok(());
+}
+
+// Columns left blank between the two images of `imprev compare`
+const COMPARE_GAP: i32 = 2;
+
+// Draw two images side by side, each fitted to half the terminal
+fn print_compare(first: &str, second: &str, options: &Options) -> Result<(), Box<dyn std::error::Error>> {
+    let (width, height) = get_terminal_size().unwrap_or_else(|e| fail(EXIT_TERMINAL, format!("Error getting terminal size: {}", e)));
+    let half = ((width - COMPARE_GAP) / 2).max(1);
+
+    let mut colormaps = Vec::new();
+    for path in [first, second] {
+        let image = load_image(path, options).unwrap_or_else(|e| fail(EXIT_BAD_IMAGE, e));
+        if image.empty() {
+            fail(EXIT_BAD_IMAGE, format!("Could not read the image: {}", path));
+        }
+        let dimensions = scale_image((half, height - 1), image_dims(&image), height_rescale(options));
+        colormaps.push(build_colormap(&image, dimensions, options)?);
+    }
+
+    // Pad the left image out to its half so the right one lines up
+    let rows = colormaps.iter().map(|colormap| colormap.len()).max().unwrap_or(0);
+    let cols = half * 2 + COMPARE_GAP;
+    let mut combined = vec![vec![Color::Transparent; cols as usize]; rows];
+    for (i, colormap) in colormaps.into_iter().enumerate() {
+        let left = i * (half + COMPARE_GAP) as usize;
+        for (r, row) in colormap.into_iter().enumerate() {
+            for (c, color) in row.into_iter().enumerate() {
+                combined[r][left + c] = color;
+            }
+        }
+    }
+    print_bitmap(combined, (cols, rows as i32), options.link.as_deref());
+    return Ok(());
+}
+
+// Draw two images over each other, the first showing left of `percent` of
+// the width and the second right of it, like a before and after slider.
+// Both are scaled to the size the first one fits the terminal at.
+fn print_split(first: &str, second: &str, percent: u8, options: &Options) -> Result<(), Box<dyn std::error::Error>> {
+    let (width, height) = get_terminal_size().unwrap_or_else(|e| fail(EXIT_TERMINAL, format!("Error getting terminal size: {}", e)));
+    let mut dimensions = None;
+    let mut colormaps = Vec::new();
+    for path in [first, second] {
+        let image = load_image(path, options).unwrap_or_else(|e| fail(EXIT_BAD_IMAGE, e));
+        if image.empty() {
+            fail(EXIT_BAD_IMAGE, format!("Could not read the image: {}", path));
+        }
+        let dimensions = *dimensions.get_or_insert_with(|| scale_image((width, height - 1), image_dims(&image), height_rescale(options)));
+        colormaps.push(build_colormap(&image, dimensions, options)?);
+    }
+    let dimensions = dimensions.unwrap_or_default();
+    let boundary = (dimensions.0 * percent as i32 / 100) as usize;
+    let second = colormaps.pop().unwrap_or_default();
+    let first = colormaps.pop().unwrap_or_default();
+    print_bitmap(split_columns(first, &second, boundary), dimensions, options.link.as_deref());
+    return Ok(());
+}
+
+// The columns of `left` before the boundary, the columns of `right` from it on
+fn split_columns(left: Vec<Vec<Color>>, right: &[Vec<Color>], boundary: usize) -> Vec<Vec<Color>> {
+    return left
+        .into_iter()
+        .zip(right)
+        .map(|(mut row, other)| {
+            let end = row.len().min(other.len());
+            let boundary = boundary.min(end);
+            row[boundary..end].copy_from_slice(&other[boundary..end]);
+            row
+        })
+        .collect();
+}
+
+fn image_dims(image: &Mat) -> (i32, i32) {
+    let size = image.size().unwrap_or_default();
+    return (size.width, size.height);
+}
+
+// Exit codes, so scripts can tell what went wrong
+const EXIT_USAGE: i32 = 1;
+const EXIT_BAD_IMAGE: i32 = 2;
+const EXIT_TERMINAL: i32 = 3;
+
+// Report the error and quit with the given exit code
+fn fail(code: i32, message: impl std::fmt::Display) -> ! {
+    stop_recording();
+    // The message would vanish along with the alternate screen
+    leave_alt_screen();
+    restore_title();
+    eprintln!("{}", message);
+    std::process::exit(code);
+}
+
+use std::env;
+const DEFAULT_HEIGHT_RESCALE: f32 = 0.5;  // Shrink the height slightly
+const DEFAULT_STILL_THRESHOLD: f64 = 1.0;  // --still-threshold, above the noise of re-encoded frames
+const DEFAULT_CHECKER_SIZE: u32 = 2;  // --checker-size, in cells across
+const DEFAULT_CHECKER_COLORS: cli::ColorPair = ((0x99, 0x99, 0x99), (0x66, 0x66, 0x66));  // Light and dark gray
+const MIN_FRAME_SIZE: (i32, i32) = (6, 3);  // Smaller frames only get the image's average color
+const FAST_FACTOR: i32 = 2;  // --fast colors one cell in this many, across and down
+const DEFAULT_TOLERANCE: u8 = 32;  // --tolerance, enough to catch JPEG noise around a flat color
+const DEFAULT_MAP_TOLERANCE: u8 = 0;  // --map-tolerance, only the exact color, sprites and logos are flat
+const RESIZE_POLL: Duration = Duration::from_millis(500);  // How often --no-signals checks the terminal size
+const INLINE_HEIGHT_SHARE: f32 = 0.5;  // Inline images are at most this much of the terminal's height
+const PIPED_SIZE: (i32, i32) = (80, 24);  // Columns and rows to draw at with no terminal anywhere
+const DEFAULT_PEAKING_COLOR: (u8, u8, u8) = (255, 0, 0);  // --focus-peaking highlight
+const DEFAULT_PEAKING_THRESHOLD: u8 = 40;  // --peaking-threshold, strong enough to skip JPEG noise
+const PEAKING_COVERAGE: u8 = 64;  // A pixel of the resized image is highlighted when a quarter of what it covers is sharp
+const DEFAULT_FACE_COLOR: (u8, u8, u8) = (0, 255, 0);  // --detect-faces boxes, apart from --rect's red
+const FPS_SMOOTHING: f64 = 0.2;  // --show-fps moves this far towards each new frame interval
+const DEFAULT_GRID_COLOR: (u8, u8, u8) = (255, 255, 255);  // --grid lines, blended halfway
+const SAMPLE_OVERSAMPLING: i32 = 2;  // --sample keeps at least this many source pixels per pixel drawn
+
+// The imprev command line
+pub fn run() -> Result<(), Box<dyn std::error::Error>> {
+    // OpenCV leaves EXR decoding off unless asked, read before the first imread
+    #[cfg(feature = "exr")]
+    if env::var_os("OPENCV_IO_ENABLE_OPENEXR").is_none() {
+        env::set_var("OPENCV_IO_ENABLE_OPENEXR", "1");
+    }
+
+    // eg usage "imprev demo.png", "imprev --play-once clip.gif" or "imprev info demo.png"
+    let cli = match cli::parse(env::args_os()) {
+        Ok(cli) => cli,
+        // --help and --version end up here too, they go to stdout and aren't failures
+        Err(e) => {
+            let _ = e.print();
+            std::process::exit(if e.use_stderr() { EXIT_USAGE } else { 0 });
+        }
+    };
+
+    match cli.command {
+        cli::Command::Render(mut options) => {
+            options.probe_cells();
+            return run_render(*options);
+        },
+        cli::Command::Info { path } => {
+            inspect::print_info(&path).unwrap_or_else(|e| fail(EXIT_BAD_IMAGE, e));
+        },
+        cli::Command::Palette { path, count, colors } => {
+            let options = Options { colors, ..Options::default() };
+            let image = load_image(&path, &options).unwrap_or_else(|e| fail(EXIT_BAD_IMAGE, e));
+            if image.empty() {
+                fail(EXIT_BAD_IMAGE, format!("Could not read the image: {}", path));
+            }
+            inspect::print_palette(&image, count, colors)?;
+        },
+        cli::Command::Compare { first, second, colors, palette, cvd, split } => {
+            let mut options = Options { colors, palette, cvd, ..Options::default() };
+            options.probe_cells();
+            match split {
+                Some(percent) => print_split(&first, &second, percent, &options)?,
+                None => print_compare(&first, &second, &options)?,
+            }
+        },
+    }
+    return Ok(());
+}
+
+// Show an image until interrupted, redrawing on resize and when it changes
+fn run_render(mut options: Options) -> Result<(), Box<dyn std::error::Error>> {
+    // Loaded once, every redraw scales it to the terminal again
+    if let Some(path) = &options.background_image {
+        let backdrop = load_image(path, &options).unwrap_or_else(|e| fail(EXIT_BAD_IMAGE, e));
+        if backdrop.empty() {
+            fail(EXIT_BAD_IMAGE, format!("Could not read the background image: {}", path));
+        }
+        options.backdrop = Some(if backdrop.channels() == 4 {
+            let mut opaque = Mat::default();
+            imgproc::cvt_color(&backdrop, &mut opaque, imgproc::COLOR_BGRA2BGR, 0)?;
+            opaque
+        } else {
+            backdrop
+        });
+    }
+
+    if options.detect_faces {
+        options.load_cascade().unwrap_or_else(|e| fail(EXIT_USAGE, e));
+    }
+    #[cfg(not(feature = "exif"))]
+    if options.metadata_overlay {
+        fail(EXIT_USAGE, "--metadata-overlay needs imprev built with the exif feature");
+    }
+
+    // The fixed palettes don't need an image at all
+    if options.dump_palette {
+        return print_active_palette(&options);
+    }
+
+    // Start from the newest image in the directory, or wait for the first one
+    let mut watch = None;
+    if let Some(dir) = options.watch_dir.clone() {
+        let mut dir_watch = watch::DirWatch::new(&dir).unwrap_or_else(|e| fail(EXIT_BAD_IMAGE, format!("Could not watch {}: {}", dir, e)));
+        let newest = match dir_watch.newest() {
+            Some(newest) => newest,
+            None => {
+                eprintln!("Waiting for an image in {}", dir);
+                dir_watch.next_image().unwrap_or_else(|| fail(EXIT_BAD_IMAGE, format!("Stopped watching {}", dir)))
+            },
+        };
+        options.image_path = newest.to_string_lossy().into_owned();
+        watch = Some(dir_watch);
+    }
+    let image_path = &options.image_path;
+    // Before anything is drawn, inline frames among them
+    read_shooting_info(image_path, &options);
+
+    // Measuring prints a couple of lines, there's nothing to redraw
+    if options.measure {
+        let image = load_image(image_path, &options).unwrap_or_else(|e| fail(EXIT_BAD_IMAGE, e));
+        if image.empty() {
+            fail(EXIT_BAD_IMAGE, format!("Could not read the image: {}", image_path));
+        }
+        return inspect::print_measure(&image, options.colors);
+    }
+
+    // Same for a single color
+    if let Some(at) = options.at {
+        let image = load_image(image_path, &options).unwrap_or_else(|e| fail(EXIT_BAD_IMAGE, e));
+        if image.empty() {
+            fail(EXIT_BAD_IMAGE, format!("Could not read the image: {}", image_path));
+        }
+        print_at(&image, at, &options).unwrap_or_else(|e| fail(EXIT_USAGE, e));
+        return Ok(());
+    }
+
+    if let Some(runs) = options.profile {
+        return print_profile(&options, runs);
+    }
+
+    // Strips are printed once, there's nothing to redraw
+    if let Some(strip) = options.strip {
+        return print_strips(&options, strip);
+    }
+
+    // Piped into a file or another program there's nobody to watch the
+    // redraws, draw once and let the reader have the output
+    if !io::stdout().is_terminal() && !options.force_tty {
+        options.once = true;
+        options.scrollback_friendly = true;
+    }
+
+    if let Err(e) = get_terminal_size() {
+        fail(EXIT_TERMINAL, format!("Error getting terminal size: {}", e));
+    }
+
+    if let Some(path) = &options.record {
+        match export::Recorder::create(path) {
+            Ok(recorder) => *RECORDER.lock().unwrap() = Some(recorder),
+            Err(e) => fail(EXIT_USAGE, format!("Could not create {}: {}", path, e)),
+        }
+    }
+
+    // Every frame of an animation or stream is drawn over the last one
+    let is_fifo = stream::is_fifo(image_path);
+    if is_fifo || animation::is_animation(image_path) {
+        options.fullscreen = true;
+    }
+    let image_path = &options.image_path;
+
+    // Inline there's nothing to redraw, the image is printed like any output
+    if !options.fullscreen {
+        let image = load_image(image_path, &options).unwrap_or_else(|e| fail(EXIT_BAD_IMAGE, e));
+        if image.empty() {
+            fail(EXIT_BAD_IMAGE, format!("Could not read the image: {}", image_path));
+        }
+        let rendered = render(&image, image_dims(&image), &options);
+        stop_recording();
+        return rendered;
+    }
+
+    // Put the terminal back the way we found it on Ctrl-C
+    if let Some(mut exit_signals) = install_signals(&[SIGINT, SIGTERM], &options) {
+        let exit_options = options.clone();
+        thread::spawn(move || {
+            if let Some(sig) = exit_signals.forever().next() {
+                stop_recording();
+                restore_terminal(&exit_options);
+                std::process::exit(128 + sig);
+            }
+        });
+    }
+
+    if options.clear_on_exit {
+        enter_alt_screen();
+    }
+    show_title(&options, image_path);
+
+    // Everything drawn from here on comes from the source, each image over the last
+    let load = |path: &str| load_image(path, &options);
+    let is_animation = animation::is_animation(image_path);
+    let mut source: Box<dyn FrameSource + '_> = if is_fifo {
+        let reader = stream::FrameReader::new(image_path, options.framing);
+        Box::new(source::Fifo::new(reader, |bytes: &[u8]| decode_image(bytes, &options)))
+    } else if is_animation {
+        animation::check_frames(image_path, options.frames()).unwrap_or_else(|e| fail(EXIT_USAGE, e));
+        Box::new(animation::Player::new(image_path, options.playback(), options.frames()))
+    } else if let Some(dir_watch) = watch {
+        Box::new(source::Watch::new(dir_watch, PathBuf::from(image_path), load))
+    } else {
+        // Only stills get refreshed, animations and streams bring their own changes
+        Box::new(source::Still::new(image_path, options.refresh, load))
+    };
+
+    let image = match source.next_frame() {
+        Some(Ok(image)) if !image.empty() => image,
+        Some(Err(e)) => fail(EXIT_BAD_IMAGE, e),
+        _ => fail(EXIT_BAD_IMAGE, format!("Could not read the image: {}", image_path)),
+    };
+    let mut shown_path = source.path();
+
+    // The scroll region is counted from the top of the screen
+    if options.scroll_region {
+        clear_screen();
+    }
+    // Every frame of an animation starts from a cleared screen, the first too
+    if is_animation {
+        start_redraw(&options);
+    }
+    if let Err(e) = render(&image, image_dims(&image), &options) {
+        eprintln!("Error: {}", e);
+    }
+
+    // Shared with the SIGWINCH thread so new content can be swapped in
+    let image = Arc::new(Mutex::new(image));
+    // With --lock-size resizes are left alone, nobody listens for them
+    if options.watching() && !options.lock_size {
+        let shared = Arc::clone(&image);
+        let thread_options = options.clone();
+        match install_signals(&[SIGWINCH], &options) {
+            Some(mut signals) => {
+                std::thread::spawn(move || {
+                    for sig in signals.forever() {
+                        match sig {
+                            SIGWINCH => redraw_resized(&shared, &thread_options),
+                            _ => unreachable!(),
+                        }
+                    }
+                });
+            },
+            // No signals, look at the size every so often instead
+            None => {
+                std::thread::spawn(move || {
+                    let mut last = get_terminal_size().ok();
+                    loop {
+                        thread::sleep(RESIZE_POLL);
+                        let size = get_terminal_size().ok();
+                        if size != last {
+                            last = size;
+                            redraw_resized(&shared, &thread_options);
+                        }
+                    }
+                });
+            },
+        }
+    }
+
+    // Drawing once still plays an animation through
+    while options.watching() || is_animation {
+        // Wait for whatever replaces the current image
+        let fresh = match source.next_frame() {
+            Some(Ok(fresh)) => fresh,
+            Some(Err(e)) => fail(EXIT_BAD_IMAGE, e),
+            None => break,
+        };
+        let path = source.path();
+        if path != shown_path {
+            if let Some(path) = &path {
+                show_title(&options, path);
+                read_shooting_info(path, &options);
+            }
+            shown_path = path;
+        }
+
+        let mut image = image.lock().unwrap();
+        *image = fresh;
+        start_redraw(&options);
+        if let Err(e) = render(&image, image_dims(&image), &options) {
+            eprintln!("Error: {}", e);
+        }
+    }
+
+    // --hold leaves the last frame of an animation up for a while (and in
+    // the recording) before exiting, --play-exit exits right away
+    if is_animation && (options.hold.is_some() || options.playback() == Some(Playback::Exit)) {
+        if let Some(hold) = options.hold {
+            thread::sleep(hold);
+        }
+        stop_recording();
+        restore_terminal(&options);
+        return Ok(());
+    }
+    if !options.watching() {
+        stop_recording();
+        restore_terminal(&options);
+        return Ok(());
+    }
+
+    // Nothing new is coming, keep the image up and redrawn on resizes
+    loop {
+        thread::sleep(Duration::from_secs(1));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tile_offsets_and_repeats() {
+        let a = Color::Indexed(1);
+        let b = Color::Indexed(2);
+        let blank = Color::Transparent;
+        let pattern = vec![vec![a, b]];
+        assert_eq!(tile(&pattern, (5, 2), (0, 0), (None, None)), vec![vec![a, b, a, b, a]; 2]);
+        assert_eq!(tile(&pattern, (3, 1), (1, 0), (None, None)), vec![vec![b, a, b]]);
+        assert_eq!(tile(&pattern, (3, 1), (-1, 0), (None, None)), vec![vec![b, a, b]]);
+        assert_eq!(
+            tile(&pattern, (5, 2), (0, 0), (Some(2), Some(1))),
+            vec![vec![a, b, a, b, blank], vec![blank; 5]]
+        );
+    }
+
+    #[test]
+    fn checkerboard_alternates_by_tile() {
+        let colors = ((1, 1, 1), (2, 2, 2));
+        let row: Vec<_> = (0..6).map(|c| checker_color(0, c, (2, 1), colors).0).collect();
+        assert_eq!(row, [1, 1, 2, 2, 1, 1]);
+        assert_eq!(checker_color(1, 0, (2, 1), colors), (2, 2, 2));
+    }
+
+    #[test]
+    fn tiny_frames_are_too_small() {
+        assert!(too_small((3, 2)));
+        assert!(too_small((80, 2)));
+        assert!(!too_small(MIN_FRAME_SIZE));
+        // The inline share of a short terminal
+        let (_, size) = frame_area((20, 5), &Options::default());
+        assert!(too_small(size));
+    }
+
+    #[test]
+    fn fullscreen_leaves_room_for_status_lines() {
+        // The exit hint, then the detection count too
+        let mut options = Options { fullscreen: true, ..Options::default() };
+        assert_eq!(frame_area((80, 24), &options).1, (80, 22));
+        options.detect_faces = true;
+        assert_eq!(frame_area((80, 24), &options).1, (80, 21));
+        options.quiet = true;
+        assert_eq!(frame_area((80, 24), &options).1, (80, 23));
+    }
+
+    #[test]
+    fn inline_frame_gets_the_shooting_info() {
+        *SHOOTING_INFO.lock().unwrap() = vec!["ISO 100".to_string()];
+        let options = Options { metadata_overlay: true, ..Options::default() };
+        let gray = Color::Rgb(128, 128, 128);
+        let colormap = vec![vec![gray; 12]; 4];
+        let mut text = String::new();
+        write_bitmap(&mut text, &colormap, (12, 4), Placement::Flow);
+        let frame = Frame { text, size: (12, 4) };
+        let mut rendered = Rendered { frame, colormap, cell_pixels: (1, 1), panels: true, detections: None };
+        add_extras(&mut rendered, Placement::Flow, Instant::now(), &options);
+        SHOOTING_INFO.lock().unwrap().clear();
+        // Bottom left, a row above the last
+        let glyphs = decode::decode_glyphs(&rendered.frame.text);
+        assert_eq!(glyphs[2].trim(), "ISO 100");
+        assert!(glyphs.iter().enumerate().all(|(row, line)| row == 2 || line.trim().is_empty()));
+    }
+
+    #[test]
+    fn fps_from_frame_interval() {
+        assert_eq!(fps_line(Some(0.04), Duration::from_micros(8300)), "25.0 fps  8.3 ms");
+        // Only one frame so far
+        assert_eq!(fps_line(None, Duration::from_millis(12)), "12.0 ms");
+    }
+
+    #[test]
+    fn sorted_images() {
+        let names = ["b.png", "c.png", "a.png"].map(PathBuf::from).to_vec();
+        assert_eq!(sort_images(names.clone(), SortBy::Name, true), ["c.png", "b.png", "a.png"].map(PathBuf::from));
+        let mut shuffled = sort_images(names.clone(), SortBy::Random, false);
+        shuffled.sort();
+        assert_eq!(shuffled, sort_images(names, SortBy::Name, false));
+    }
+
+    #[test]
+    fn cell_aspect_from_pixels() {
+        assert_eq!(cell_aspect((80, 24), (640, 384)), Some(0.5));
+        assert_eq!(cell_aspect((100, 50), (1000, 500)), Some(1.0));
+        // The whole screen's width for an 80 column window
+        assert_eq!(cell_aspect((80, 24), (7680, 384)), None);
+    }
+
+    #[test]
+    fn scale_image_fits_and_keeps_aspect() {
+        let cases = [
+            ((80, 24), (100, 100)),   // Square image, wide terminal
+            ((40, 40), (400, 100)),   // Wide image, square terminal
+            ((80, 24), (100, 400)),   // Tall image, wide terminal
+            ((200, 50), (1920, 1080)),
+            ((30, 60), (640, 480)),
+        ];
+        for &(terminal, image) in &cases {
+            let (w, h) = scale_image(terminal, image, DEFAULT_HEIGHT_RESCALE);
+            assert!(w <= terminal.0 && h <= terminal.1, "{:?} in {:?} gave {}x{}", image, terminal, w, h);
+            // One side fills the terminal
+            assert!(w == terminal.0 || h == terminal.1, "{:?} in {:?} gave {}x{}", image, terminal, w, h);
+            // Cells are twice as tall as wide, so the image is twice as wide in cells.
+            // Truncating can be off by up to a cell on the side that didn't fill.
+            let expected = image.0 as f32 / (image.1 as f32 * DEFAULT_HEIGHT_RESCALE);
+            let tolerance = expected * (1.0 / w as f32 + 1.0 / h as f32);
+            assert!((w as f32 / h as f32 - expected).abs() <= tolerance, "{:?} in {:?} gave {}x{}", image, terminal, w, h);
+        }
+        assert_eq!(scale_image((80, 24), (100, 100), 0.5), (48, 24));
+        assert_eq!(scale_image((40, 40), (400, 100), 0.5), (40, 5));
+        assert_eq!(scale_image((80, 24), (100, 400), 0.5), (12, 24));
+        // A height scale of 1 means square cells
+        assert_eq!(scale_image((50, 50), (200, 100), 1.0), (50, 25));
+        // Extreme shapes never collapse to nothing
+        assert_eq!(scale_image((80, 24), (10000, 1), 0.5), (80, 1));
+        assert_eq!(scale_image((80, 24), (1, 10000), 0.5), (1, 24));
+    }
+
+    #[test]
+    fn shrinking_frames_clear_the_last_one() {
+        // Terminal sizes from a run of SIGWINCHs, and whether each one needs a clear
+        let sizes = [
+            ((80, 24), false),
+            ((80, 24), false),
+            ((100, 30), false),  // Growing covers everything that was there
+            ((60, 30), true),
+            ((60, 20), true),
+            ((70, 10), true),    // Wider but shorter still leaves rows behind
+            ((70, 10), false),
+        ];
+        let mut last = None;
+        for (size, clears) in sizes {
+            assert_eq!(uncovers_last(last, size), clears, "{:?} after {:?}", size, last);
+            last = Some(size);
+        }
+    }
+
+    #[test]
+    fn bitmap_frames_jump_over_transparent_cells() {
+        let red = Color::Indexed(196);
+        let mut out = String::new();
+        write_bitmap(&mut out, &[vec![red, Color::Transparent, Color::Transparent, red]], (4, 1), Placement::Flow);
+        assert_eq!(out, "\x1B[48;5;196m \x1B[0m\x1B[2C\x1B[48;5;196m \x1B[0m\x1B[K\n\x1B[0m");
+    }
+
+    #[test]
+    fn placed_frames_move_to_each_row() {
+        let red = Color::Indexed(196);
+        let mut out = String::new();
+        write_bitmap(&mut out, &[vec![red], vec![Color::Transparent]], (1, 2), Placement::At(4, 2));
+        // No newlines and no erasing, the rest of the screen is left alone
+        assert_eq!(out, "\x1B[3;5H\x1B[48;5;196m \x1B[0m\x1B[4;5H\x1B[0m");
+
+        let mut out = String::new();
+        write_bitmap(&mut out, &[vec![Color::Transparent, red]], (2, 1), Placement::Plain);
+        assert_eq!(out, " \x1B[48;5;196m \x1B[0m\n\x1B[0m");
+    }
+
+    #[test]
+    fn sample_reduction_keeps_enough_pixels() {
+        // 80x24 cells want 160x96 pixels with room to spare
+        assert_eq!(sample_reduction((20000, 15000), (80, 24)), 8);
+        assert_eq!(sample_reduction((1000, 800), (80, 24)), 4);
+        assert_eq!(sample_reduction((400, 400), (80, 24)), 2);
+        assert_eq!(sample_reduction((300, 100), (80, 24)), 1);
+    }
+
+    #[test]
+    fn fold_stacks_strips() {
+        let (a, b, c) = (Color::Indexed(1), Color::Indexed(2), Color::Indexed(3));
+        let blank = Color::Transparent;
+        let wide = vec![vec![a, b, c], vec![c, b, a]];
+        assert_eq!(
+            fold(wide.clone(), 2, 1),
+            vec![vec![a, b], vec![c, b], vec![blank; 2], vec![c, blank], vec![a, blank]]
+        );
+        assert_eq!(fold(wide.clone(), 3, 1), wide);
+        // Two pixel rows per cell, the one row strips are padded to two
+        assert_eq!(fold(vec![vec![a, b]], 1, 2), vec![vec![a], vec![blank], vec![blank], vec![blank], vec![b], vec![blank]]);
+    }
+
+    #[test]
+    fn split_takes_each_side() {
+        let (a, b) = (Color::Indexed(1), Color::Indexed(2));
+        assert_eq!(split_columns(vec![vec![a; 4]; 2], &[vec![b; 4], vec![b; 4]], 1), vec![vec![a, b, b, b]; 2]);
+        assert_eq!(split_columns(vec![vec![a; 2]], &[vec![b; 2]], 0), vec![vec![b, b]]);
+        assert_eq!(split_columns(vec![vec![a; 2]], &[vec![b; 2]], 2), vec![vec![a, a]]);
+    }
+
+    #[test]
+    fn grid_lines_and_thirds() {
+        let lines = |grid: Grid, length: usize| (0..length).filter(|&i| grid.on_line(i, length)).collect::<Vec<_>>();
+        assert_eq!(lines(Grid::Every(4), 10), vec![4, 8]);
+        assert_eq!(lines(Grid::Thirds, 9), vec![3, 6]);
+        assert_eq!(lines(Grid::Thirds, 2), Vec::<usize>::new());
+        assert_eq!(Grid::parse("thirds"), Ok(Grid::Thirds));
+        assert!(Grid::parse("0").is_err());
+
+        // Two pixels per cell across, the second cell column is a line
+        let options = Options { colors: ColorMode::Truecolor, ..Options::default() };
+        let black = Color::Rgb(0, 0, 0);
+        let grey = Color::Rgb(127, 127, 127);
+        let overlaid = overlay_grid(vec![vec![black; 4]], Grid::Every(1), (2, 1), &options);
+        assert_eq!(overlaid, vec![vec![black, black, grey, grey]]);
+    }
+
+    #[test]
+    fn banded_size_keeps_aspect_and_margin() {
+        // 80x24 cells want 160x96 pixels
+        assert_eq!(banded_size((16000, 9000), (80, 24)), (171, 96));
+        assert_eq!(banded_size((1000, 4000), (80, 24)), (160, 640));
+        // Small images are left alone
+        assert_eq!(banded_size((100, 50), (80, 24)), (100, 50));
+    }
+
+    #[test]
+    fn upscale_repeats_cells() {
+        let a = Color::Indexed(1);
+        let b = Color::Indexed(2);
+        let c = Color::Indexed(3);
+        let d = Color::Indexed(4);
+        let small = vec![vec![a, b], vec![c, d]];
+        assert_eq!(upscale_nearest(&small, (4, 3)), vec![vec![a, a, b, b], vec![a, a, b, b], vec![c, c, d, d]]);
+        // Odd sizes keep the last column
+        assert_eq!(upscale_nearest(&small, (3, 2)), vec![vec![a, a, b], vec![c, c, d]]);
+    }
+
+    #[test]
+    fn clamp_cells_keeps_proportions() {
+        assert_eq!(clamp_cells((80, 24), 10_000), (80, 24));
+        assert_eq!(clamp_cells((400, 100), 10_000), (200, 50));
+        let (w, h) = clamp_cells((333, 77), 1_000);
+        assert!(w * h <= 1_000);
+        assert!(((w as f32 / h as f32) - 333.0 / 77.0).abs() < 0.3);
+        assert_eq!(clamp_cells((1000, 1), 10), (10, 1));
+    }
+}
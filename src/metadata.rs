@@ -0,0 +1,161 @@
+// --metadata-overlay: a photo's shooting info from its EXIF, drawn in a small
+// panel over a corner of the frame
+use crate::palette::Color;
+use crate::Placement;
+
+// Cells left between the panel and the edges of the frame
+const PANEL_MARGIN: i32 = 1;
+
+// Which corner of the frame the panel goes in
+#[derive(Clone, Copy, Debug, Default, PartialEq, clap::ValueEnum)]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    #[default]
+    BottomLeft,
+    BottomRight,
+}
+
+// Camera, lens, exposure and date, a line each, leaving out what the file
+// doesn't say. Errors for files without EXIF.
+#[cfg(feature = "exif")]
+pub fn shooting_info(path: &str) -> Result<Vec<String>, String> {
+    let file = std::fs::File::open(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+    let exif = exif::Reader::new()
+        .read_from_container(&mut std::io::BufReader::new(file))
+        .map_err(|e| format!("No EXIF in {}: {}", path, e))?;
+    let field = |tag: exif::Tag| {
+        let field = exif.get_field(tag, exif::In::PRIMARY)?;
+        // Text fields come quoted
+        let value = field.display_value().with_unit(&exif).to_string();
+        let value = value.trim_matches('"').trim();
+        return (!value.is_empty()).then(|| value.to_string());
+    };
+
+    // Most models already start with the make
+    let camera = match (field(exif::Tag::Make), field(exif::Tag::Model)) {
+        (Some(make), Some(model)) if !model.starts_with(&make) => Some(format!("{} {}", make, model)),
+        (_, Some(model)) => Some(model),
+        (make, None) => make,
+    };
+    let exposure: Vec<String> = [
+        field(exif::Tag::PhotographicSensitivity).map(|iso| format!("ISO {}", iso)),
+        field(exif::Tag::ExposureTime),
+        field(exif::Tag::FNumber),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    let exposure = (!exposure.is_empty()).then(|| exposure.join("  "));
+    let lines = [camera, field(exif::Tag::LensModel), exposure, field(exif::Tag::DateTimeOriginal)];
+    return Ok(lines.into_iter().flatten().collect());
+}
+
+// The top left cell of a `panel` sized panel in the corner of a `frame`
+// sized frame, both in cells
+pub fn panel_origin(corner: Corner, frame: (i32, i32), panel: (i32, i32)) -> (i32, i32) {
+    let right = (frame.0 - panel.0 - PANEL_MARGIN).max(0);
+    let bottom = (frame.1 - panel.1 - PANEL_MARGIN).max(0);
+    let (left, top) = (PANEL_MARGIN.min(right), PANEL_MARGIN.min(bottom));
+    match corner {
+        Corner::TopLeft => (left, top),
+        Corner::TopRight => (right, top),
+        Corner::BottomLeft => (left, bottom),
+        Corner::BottomRight => (right, bottom),
+    }
+}
+
+// Append the panel to a frame that was just written with `placement`. Each
+// cell of it is the cell under it (from `under`, by column and row) darkened
+// halfway, with the text in white on top, so the picture still shows through.
+// `quantize` brings colors into the palette the frame is drawn in. Plain
+// frames can't be drawn over, the lines go below them instead.
+pub fn write_panel<U, Q>(out: &mut String, lines: &[String], corner: Corner, frame: (i32, i32), placement: Placement, under: U, quantize: Q)
+where
+    U: Fn(i32, i32) -> Color,
+    Q: Fn(u8, u8, u8) -> Color,
+{
+    if lines.is_empty() {
+        return;
+    }
+    let text = quantize(255, 255, 255).foreground();
+    if placement == Placement::Plain {
+        for line in lines {
+            out.push_str(&format!("{}{}\x1B[0m\n", text, line));
+        }
+        return;
+    }
+
+    // A space either side, cut off where the frame ends
+    let longest = lines.iter().map(|line| line.chars().count()).max().unwrap_or(0) as i32;
+    let size = ((longest + 2).min(frame.0), (lines.len() as i32).min(frame.1));
+    let (left, top) = panel_origin(corner, frame, size);
+    if placement == Placement::Flow {
+        // The frame ends on the line below its last row
+        out.push_str("\x1B7");
+    }
+    for (i, line) in lines.iter().take(size.1 as usize).enumerate() {
+        let row = top + i as i32;
+        match placement {
+            Placement::At(x, y) => out.push_str(&format!("\x1B[{};{}H", y + row + 1, x + left + 1)),
+            _ => {
+                out.push_str(&format!("\x1B8\x1B[{}A\r", frame.1 - row));
+                if left > 0 {
+                    out.push_str(&format!("\x1B[{}C", left));
+                }
+            },
+        }
+        let padded: Vec<char> = format!(" {:width$} ", line, width = longest as usize).chars().collect();
+        for (c, ch) in padded.into_iter().take(size.0 as usize).enumerate() {
+            let (r, g, b) = match under(left + c as i32, row) {
+                // The terminal's own background, assumed dark
+                Color::Transparent => (0, 0, 0),
+                color => color.to_rgb(),
+            };
+            out.push_str(&format!("{}{}{}", quantize(r / 2, g / 2, b / 2).background(), text, ch));
+        }
+        out.push_str("\x1B[0m");
+    }
+    if placement == Placement::Flow {
+        out.push_str("\x1B8");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode::{decode, decode_glyphs};
+
+    #[test]
+    fn panel_sits_in_its_corner() {
+        assert_eq!(panel_origin(Corner::TopLeft, (80, 24), (20, 4)), (1, 1));
+        assert_eq!(panel_origin(Corner::BottomRight, (80, 24), (20, 4)), (59, 19));
+        // No room for the margin
+        assert_eq!(panel_origin(Corner::BottomLeft, (20, 4), (20, 4)), (0, 0));
+
+        let gray = Color::Rgb(200, 100, 50);
+        let mut out = String::new();
+        let lines = vec!["ab".to_string()];
+        write_panel(&mut out, &lines, Corner::BottomRight, (8, 3), Placement::At(0, 0), |_, _| gray, Color::Rgb);
+        let grid = decode(&out);
+        // Four cells wide, one in from the right and bottom
+        assert_eq!(grid[1][3..7], [Color::Rgb(100, 50, 25); 4]);
+        assert!(grid[1][..3].iter().all(|&color| color == Color::Transparent));
+    }
+
+    #[test]
+    fn flow_panel_goes_back_up_over_the_frame() {
+        let gray = Color::Rgb(200, 100, 50);
+        let mut out = String::new();
+        crate::write_bitmap(&mut out, &vec![vec![gray; 6]; 3], (6, 3), Placement::Flow);
+        let lines = vec!["ab".to_string()];
+        write_panel(&mut out, &lines, Corner::TopLeft, (6, 3), Placement::Flow, |_, _| gray, Color::Rgb);
+        // Whatever comes next starts below the frame again
+        out.push('x');
+        let grid = decode(&out);
+        assert_eq!(grid[1][1..5], [Color::Rgb(100, 50, 25); 4]);
+        assert_eq!(grid[1][0], gray);
+        assert_eq!(grid[0], vec![gray; 6]);
+        assert_eq!(decode_glyphs(&out), vec!["      ", "  ab  ", "      ", "x     "]);
+    }
+}
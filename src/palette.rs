@@ -0,0 +1,381 @@
+use std::fs;
+
+// A cell color, in whichever form the terminal escape needs it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Color {
+    Indexed(u8),      // xterm 256 color index
+    Rgb(u8, u8, u8),  // Truecolor
+    Transparent,      // Fully transparent, left unpainted so the terminal shows through
+}
+
+// The 16 standard colors as xterm draws them by default
+const ANSI_16: [(u8, u8, u8); 16] = [
+    (0, 0, 0), (205, 0, 0), (0, 205, 0), (205, 205, 0),
+    (0, 0, 238), (205, 0, 205), (0, 205, 205), (229, 229, 229),
+    (127, 127, 127), (255, 0, 0), (0, 255, 0), (255, 255, 0),
+    (92, 92, 255), (255, 0, 255), (0, 255, 255), (255, 255, 255),
+];
+
+// Channel levels of the 6x6x6 cube in indices 16-231
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+impl Color {
+    // The RGB value a terminal shows for this color
+    pub fn to_rgb(self) -> (u8, u8, u8) {
+        match self {
+            Color::Rgb(r, g, b) => (r, g, b),
+            // Nothing gets drawn, assume a dark terminal background
+            Color::Transparent => (0, 0, 0),
+            Color::Indexed(i) if i < 16 => ANSI_16[i as usize],
+            Color::Indexed(i) if i < 232 => {
+                let i = i - 16;
+                (CUBE_LEVELS[(i / 36) as usize], CUBE_LEVELS[(i / 6 % 6) as usize], CUBE_LEVELS[(i % 6) as usize])
+            },
+            Color::Indexed(i) => {
+                let level = 8 + 10 * (i - 232);
+                (level, level, level)
+            },
+        }
+    }
+
+    // SGR escape that paints the cell background in this color
+    pub fn background(self) -> String {
+        match self {
+            // The basic 16 have plain SGR codes that work without 256 color support
+            Color::Indexed(index) if index < 8 => format!("\x1B[{}m", 40 + index),
+            Color::Indexed(index) if index < 16 => format!("\x1B[{}m", 100 + index - 8),
+            Color::Indexed(index) => format!("\x1B[48;5;{}m", index),
+            Color::Rgb(r, g, b) => format!("\x1B[48;2;{};{};{}m", r, g, b),
+            Color::Transparent => "\x1B[49m".to_string(),
+        }
+    }
+
+    // SGR escape that draws text in this color
+    pub fn foreground(self) -> String {
+        match self {
+            Color::Indexed(index) if index < 8 => format!("\x1B[{}m", 30 + index),
+            Color::Indexed(index) if index < 16 => format!("\x1B[{}m", 90 + index - 8),
+            Color::Indexed(index) => format!("\x1B[38;5;{}m", index),
+            Color::Rgb(r, g, b) => format!("\x1B[38;2;{};{};{}m", r, g, b),
+            Color::Transparent => "\x1B[39m".to_string(),
+        }
+    }
+}
+
+// How many colors the terminal can show, which picks the quantizer
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ColorMode {
+    Ansi8,
+    Ansi16,
+    #[default]
+    Xterm256,
+    Truecolor,
+}
+
+impl ColorMode {
+    // Parse the --colors value
+    pub fn parse(value: &str) -> Result<ColorMode, String> {
+        match value {
+            "8" => Ok(ColorMode::Ansi8),
+            "16" => Ok(ColorMode::Ansi16),
+            "256" => Ok(ColorMode::Xterm256),
+            "truecolor" | "24bit" => Ok(ColorMode::Truecolor),
+            _ => Err(format!("Invalid color mode: {} (expected 8, 16, 256 or truecolor)", value)),
+        }
+    }
+
+    // Pick a mode from the terminfo "colors" capability
+    pub fn from_color_count(count: i32) -> ColorMode {
+        match count {
+            n if n >= 1 << 24 => ColorMode::Truecolor,
+            n if n >= 256 => ColorMode::Xterm256,
+            n if n >= 16 => ColorMode::Ansi16,
+            _ => ColorMode::Ansi8,
+        }
+    }
+
+    // Every color the mode can draw, None for truecolor which has no palette
+    pub fn entries(self) -> Option<Vec<Color>> {
+        let count = match self {
+            ColorMode::Ansi8 => 8,
+            ColorMode::Ansi16 => 16,
+            ColorMode::Xterm256 => 256,
+            ColorMode::Truecolor => return None,
+        };
+        return Some((0..count).map(|i| Color::Indexed(i as u8)).collect());
+    }
+
+    // Map an RGB pixel to the closest color this mode can show
+    pub fn quantize(self, r: u8, g: u8, b: u8) -> Color {
+        return self.quantize_with(r, g, b, Metric::Rgb);
+    }
+
+    // Same as quantize, measuring closeness with the given metric
+    pub fn quantize_with(self, r: u8, g: u8, b: u8, metric: Metric) -> Color {
+        match self {
+            ColorMode::Ansi8 => Color::Indexed(nearest_index(&ANSI_16[..8], r, g, b, metric) as u8),
+            ColorMode::Ansi16 => Color::Indexed(nearest_index(&ANSI_16, r, g, b, metric) as u8),
+            ColorMode::Xterm256 if metric == Metric::Rgb => Color::Indexed(rgb_to_256_color(r, g, b)),
+            ColorMode::Xterm256 => Color::Indexed(nearest_256_color(r, g, b, metric)),
+            ColorMode::Truecolor => Color::Rgb(r, g, b),
+        }
+    }
+}
+
+// How the difference between two colors is measured when picking the nearest
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Metric {
+    #[default]
+    Rgb,  // Squared euclidean distance of the channel values
+    Lab,  // Squared euclidean distance in CIE Lab, closer to how different colors look
+}
+
+impl Metric {
+    pub fn distance(self, a: (u8, u8, u8), b: (u8, u8, u8)) -> f32 {
+        let squared = |a: (f32, f32, f32), b: (f32, f32, f32)| (a.0 - b.0).powi(2) + (a.1 - b.1).powi(2) + (a.2 - b.2).powi(2);
+        match self {
+            Metric::Rgb => squared((a.0 as f32, a.1 as f32, a.2 as f32), (b.0 as f32, b.1 as f32, b.2 as f32)),
+            Metric::Lab => squared(to_lab(a), to_lab(b)),
+        }
+    }
+}
+
+// CIE Lab under D65, the same conversion cvt_color does for COLOR_BGR2Lab on
+// float images. Worked out here since it runs for every pixel and candidate.
+pub fn to_lab((r, g, b): (u8, u8, u8)) -> (f32, f32, f32) {
+    let linear = |v: u8| {
+        let v = v as f32 / 255.0;
+        if v <= 0.04045 { v / 12.92 } else { ((v + 0.055) / 1.055).powf(2.4) }
+    };
+    let (r, g, b) = (linear(r), linear(g), linear(b));
+    let x = (0.412453 * r + 0.357580 * g + 0.180423 * b) / 0.950456;
+    let y = 0.212671 * r + 0.715160 * g + 0.072169 * b;
+    let z = (0.019334 * r + 0.119193 * g + 0.950227 * b) / 1.088754;
+    let f = |t: f32| if t > 0.008856 { t.cbrt() } else { 7.787 * t + 16.0 / 116.0 };
+    let lightness = if y > 0.008856 { 116.0 * f(y) - 16.0 } else { 903.3 * y };
+    return (lightness, 500.0 * (f(x) - f(y)), 200.0 * (f(y) - f(z)));
+}
+
+// Convert RGB to a color index (0-255)
+pub fn rgb_to_256_color(r: u8, g: u8, b: u8) -> u8 {
+    if r == g && g == b {
+        if r < 8 {
+            return 16;
+        }
+        if r > 248 {
+            return 231;
+        }
+        return (r - 8) / 247 * 24 + 232;
+    }
+    16 + (36 * (r / 51)) + (6 * (g / 51)) + (b / 51)
+}
+
+// Pick between the nearest cube color and the nearest gray of the xterm
+// palette. The 16 base colors are left out, terminals theme them.
+fn nearest_256_color(r: u8, g: u8, b: u8, metric: Metric) -> u8 {
+    let level = |v: u8| (0..CUBE_LEVELS.len()).min_by_key(|&i| (CUBE_LEVELS[i] as i32 - v as i32).abs()).unwrap_or(0) as u8;
+    let cube = 16 + 36 * level(r) + 6 * level(g) + level(b);
+    let distance = |index: u8| metric.distance(Color::Indexed(index).to_rgb(), (r, g, b));
+    return std::iter::once(cube).chain(232..=255).min_by(|&i, &j| distance(i).total_cmp(&distance(j))).unwrap_or(cube);
+}
+
+// Index of the closest color by the given metric
+pub fn nearest_index(colors: &[(u8, u8, u8)], r: u8, g: u8, b: u8, metric: Metric) -> usize {
+    let distance = |&color: &(u8, u8, u8)| metric.distance(color, (r, g, b));
+    return (0..colors.len()).min_by(|&i, &j| distance(&colors[i]).total_cmp(&distance(&colors[j]))).unwrap_or(0);
+}
+
+// A user supplied set of colors that every pixel gets snapped to
+#[derive(Clone, Debug, PartialEq)]
+pub struct Palette {
+    pub colors: Vec<(u8, u8, u8)>,
+}
+
+impl Palette {
+    // Read hex colors separated by whitespace or commas, eg "#1a1c2c, 5d275d"
+    pub fn load(path: &str) -> Result<Palette, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read palette file {}: {}", path, e))?;
+        let palette = Palette::parse(&contents)?;
+        if palette.colors.is_empty() {
+            return Err(format!("No colors found in palette file: {}", path));
+        }
+        return Ok(palette);
+    }
+
+    pub fn parse(contents: &str) -> Result<Palette, String> {
+        let colors = contents
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|token| !token.is_empty())
+            .map(parse_hex)
+            .collect::<Result<Vec<_>, String>>()?;
+        return Ok(Palette { colors });
+    }
+
+    // Nearest palette entry, the pixel itself if the palette is empty
+    pub fn nearest(&self, r: u8, g: u8, b: u8) -> (u8, u8, u8) {
+        return self.nearest_with(r, g, b, Metric::Rgb);
+    }
+
+    pub fn nearest_with(&self, r: u8, g: u8, b: u8, metric: Metric) -> (u8, u8, u8) {
+        if self.colors.is_empty() {
+            return (r, g, b);
+        }
+        return self.colors[nearest_index(&self.colors, r, g, b, metric)];
+    }
+}
+
+// How many colors --adaptive-palette picks, as many as the xterm palette has
+pub const ADAPTIVE_PALETTE_SIZE: usize = 256;
+
+// Median cut: start with one box holding every pixel, keep splitting the box
+// with the widest channel range at the median of that channel, then use the
+// average of each box
+pub fn median_cut(pixels: &[(u8, u8, u8)], size: usize) -> Palette {
+    let channel = |p: &(u8, u8, u8), i: usize| [p.0, p.1, p.2][i];
+    // Widest channel of a box and how wide it is
+    let widest = |pixels: &[(u8, u8, u8)]| {
+        (0..3)
+            .map(|i| {
+                let (low, high) = pixels.iter().fold((255, 0), |(low, high), p| (channel(p, i).min(low), channel(p, i).max(high)));
+                (i, high.saturating_sub(low))
+            })
+            .max_by_key(|&(_, range)| range)
+            .unwrap_or((0, 0))
+    };
+
+    let mut boxes = Vec::new();
+    if !pixels.is_empty() && size > 0 {
+        boxes.push(pixels.to_vec());
+    }
+    while boxes.len() < size {
+        // Boxes of a single color can't be split any further
+        let candidate = (0..boxes.len())
+            .map(|i| (i, widest(&boxes[i])))
+            .filter(|&(_, (_, range))| range > 0)
+            .max_by_key(|&(_, (_, range))| range);
+        let (index, (axis, _)) = match candidate {
+            Some(candidate) => candidate,
+            None => break,
+        };
+        let mut split = boxes.swap_remove(index);
+        split.sort_unstable_by_key(|p| channel(p, axis));
+        // Cut next to the median rather than through a run of equal values,
+        // so one color never ends up averaged into two boxes
+        let median = channel(&split[split.len() / 2], axis);
+        let below = split.partition_point(|p| channel(p, axis) < median);
+        let cut = if below > 0 { below } else { split.partition_point(|p| channel(p, axis) <= median) };
+        let upper = split.split_off(cut);
+        boxes.push(split);
+        boxes.push(upper);
+    }
+
+    let colors = boxes
+        .iter()
+        .map(|pixels| {
+            let average = |i: usize| (pixels.iter().map(|p| channel(p, i) as u64).sum::<u64>() / pixels.len() as u64) as u8;
+            (average(0), average(1), average(2))
+        })
+        .collect();
+    return Palette { colors };
+}
+
+// Parse "#rrggbb", "rrggbb" or the short "#rgb" form
+pub fn parse_hex(token: &str) -> Result<(u8, u8, u8), String> {
+    let hex = token.trim_start_matches('#');
+    let invalid = || format!("Invalid hex color: {}", token);
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(invalid());
+    }
+    let channel = |s: &str| u8::from_str_radix(s, 16).map_err(|_| invalid());
+    match hex.len() {
+        6 => Ok((channel(&hex[0..2])?, channel(&hex[2..4])?, channel(&hex[4..6])?)),
+        3 => {
+            let short = |i: usize| channel(&hex[i..i + 1]).map(|v| v * 17);
+            Ok((short(0)?, short(1)?, short(2)?))
+        }
+        _ => Err(invalid()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nearest_in_four_color_palette() {
+        let palette = Palette::parse("#000000, #ffffff\n#ff0000 0000ff").unwrap();
+        assert_eq!(palette.colors.len(), 4);
+        assert_eq!(palette.nearest(10, 20, 5), (0, 0, 0));
+        assert_eq!(palette.nearest(230, 240, 220), (255, 255, 255));
+        assert_eq!(palette.nearest(200, 40, 60), (255, 0, 0));
+        assert_eq!(palette.nearest(30, 20, 180), (0, 0, 255));
+    }
+
+    #[test]
+    fn indexed_to_rgb() {
+        assert_eq!(Color::Indexed(1).to_rgb(), (205, 0, 0));
+        assert_eq!(Color::Indexed(16).to_rgb(), (0, 0, 0));
+        assert_eq!(Color::Indexed(196).to_rgb(), (255, 0, 0));
+        assert_eq!(Color::Indexed(231).to_rgb(), (255, 255, 255));
+        assert_eq!(Color::Indexed(255).to_rgb(), (238, 238, 238));
+    }
+
+    #[test]
+    fn quantize_per_mode() {
+        assert_eq!(ColorMode::Ansi16.entries().map(|e| e.len()), Some(16));
+        assert_eq!(ColorMode::Xterm256.entries().unwrap()[255], Color::Indexed(255));
+        assert_eq!(ColorMode::Truecolor.entries(), None);
+        assert_eq!(ColorMode::Ansi8.quantize(250, 10, 10), Color::Indexed(1));
+        assert_eq!(ColorMode::Ansi16.quantize(250, 10, 10), Color::Indexed(9));
+        assert_eq!(ColorMode::Xterm256.quantize(255, 0, 0), Color::Indexed(196));
+        assert_eq!(ColorMode::Truecolor.quantize(1, 2, 3), Color::Rgb(1, 2, 3));
+        assert_eq!(ColorMode::from_color_count(8), ColorMode::Ansi8);
+        assert_eq!(ColorMode::from_color_count(256), ColorMode::Xterm256);
+        assert_eq!(ColorMode::from_color_count(16777216), ColorMode::Truecolor);
+    }
+
+    #[test]
+    fn median_cut_separates_clusters() {
+        let mut pixels = vec![(10, 10, 10); 50];
+        pixels.extend(vec![(12, 14, 10); 50]);
+        pixels.extend(vec![(200, 40, 40); 30]);
+        pixels.extend(vec![(40, 40, 220); 20]);
+        let palette = median_cut(&pixels, 6);
+        assert!(palette.colors.len() <= 6);
+        // Every cluster ends up with an entry of its own
+        for &(r, g, b) in &[(10, 10, 10), (200, 40, 40), (40, 40, 220)] {
+            let (pr, pg, pb) = palette.nearest(r, g, b);
+            let close = |a: u8, b: u8| (a as i32 - b as i32).abs() <= 4;
+            assert!(close(r, pr) && close(g, pg) && close(b, pb), "{:?} got {:?}", (r, g, b), (pr, pg, pb));
+        }
+        // Never more colors than there are distinct pixels
+        assert_eq!(median_cut(&[(1, 2, 3); 10], 256).colors, vec![(1, 2, 3)]);
+        assert!(median_cut(&[], 256).colors.is_empty());
+    }
+
+    #[test]
+    fn lab_and_rgb_disagree() {
+        // Periwinkle is nearer gray by the numbers, but looks like a light blue
+        let palette = Palette::parse("#808080 #0000ff").unwrap();
+        assert_eq!(palette.nearest_with(128, 128, 255, Metric::Rgb), (128, 128, 128));
+        assert_eq!(palette.nearest_with(128, 128, 255, Metric::Lab), (0, 0, 255));
+        // Orange sits halfway between red and yellow in RGB, Lab puts it closer to red
+        assert_eq!(nearest_index(&[(255, 0, 0), (255, 255, 0)], 255, 128, 0, Metric::Rgb), 1);
+        assert_eq!(nearest_index(&[(255, 0, 0), (255, 255, 0)], 255, 128, 0, Metric::Lab), 0);
+
+        let (l, a, b) = to_lab((255, 255, 255));
+        assert!((l - 100.0).abs() < 0.1 && a.abs() < 0.1 && b.abs() < 0.1, "{:?}", (l, a, b));
+        // Grays stay grays, the cube's mid gray and the ramp are both candidates
+        assert_eq!(ColorMode::Xterm256.quantize_with(255, 0, 0, Metric::Lab), Color::Indexed(196));
+        assert_eq!(ColorMode::Xterm256.quantize_with(128, 128, 128, Metric::Lab), Color::Indexed(244));
+    }
+
+    #[test]
+    fn parse_hex_forms() {
+        assert_eq!(parse_hex("#1a1c2c"), Ok((0x1a, 0x1c, 0x2c)));
+        assert_eq!(parse_hex("fff"), Ok((255, 255, 255)));
+        assert!(parse_hex("#12345").is_err());
+        assert!(parse_hex("zzzzzz").is_err());
+    }
+}
@@ -0,0 +1,81 @@
+use std::sync::Mutex;
+use std::time::Duration;
+
+// Every timing of each stage, in the order the stages first ran
+pub type Stages = Vec<(String, Vec<Duration>)>;
+
+// Collected while --profile runs
+static SAMPLES: Mutex<Option<Stages>> = Mutex::new(None);
+
+pub fn start() {
+    *SAMPLES.lock().unwrap() = Some(Vec::new());
+}
+
+// Add a timing, ignored unless profiling
+pub fn record(stage: &str, elapsed: Duration) {
+    let mut samples = SAMPLES.lock().unwrap();
+    let samples = match samples.as_mut() {
+        Some(samples) => samples,
+        None => return,
+    };
+    match samples.iter_mut().find(|(name, _)| name == stage) {
+        Some((_, durations)) => durations.push(elapsed),
+        None => samples.push((stage.to_string(), vec![elapsed])),
+    }
+}
+
+// Stop profiling and hand back everything recorded
+pub fn finish() -> Stages {
+    return SAMPLES.lock().unwrap().take().unwrap_or_default();
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Summary {
+    pub mean: Duration,
+    pub median: Duration,
+    pub p95: Duration,
+}
+
+// Mean, median and 95th percentile (nearest rank) of some timings
+pub fn summarize(samples: &[Duration]) -> Summary {
+    if samples.is_empty() {
+        return Summary { mean: Duration::ZERO, median: Duration::ZERO, p95: Duration::ZERO };
+    }
+    let mut sorted = samples.to_vec();
+    sorted.sort();
+    let mean = sorted.iter().sum::<Duration>() / sorted.len() as u32;
+    let rank = |share: f64| sorted[((share * sorted.len() as f64).ceil() as usize).clamp(1, sorted.len()) - 1];
+    return Summary { mean, median: rank(0.5), p95: rank(0.95) };
+}
+
+pub fn print_table(stages: &Stages, runs: u32, bytes: usize) {
+    println!("{:>10}  {:>10}  {:>10}  {:>10}", "stage", "mean", "median", "p95");
+    for (stage, samples) in stages {
+        let summary = summarize(samples);
+        println!(
+            "{:>10}  {:>10}  {:>10}  {:>10}",
+            stage,
+            format!("{:.2?}", summary.mean),
+            format!("{:.2?}", summary.median),
+            format!("{:.2?}", summary.p95),
+        );
+    }
+    println!("{} runs, {} bytes per frame", runs, bytes / runs.max(1) as usize);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summary_of_timings() {
+        let ms = Duration::from_millis;
+        let samples: Vec<Duration> = (1..=20).rev().map(ms).collect();
+        let summary = summarize(&samples);
+        assert_eq!(summary.median, ms(10));
+        assert_eq!(summary.p95, ms(19));
+        assert_eq!(summary.mean, Duration::from_micros(10500));
+        assert_eq!(summarize(&[ms(7)]), Summary { mean: ms(7), median: ms(7), p95: ms(7) });
+        assert_eq!(summarize(&[]).p95, Duration::ZERO);
+    }
+}
@@ -0,0 +1,169 @@
+// Where the images drawn come from. The CLI draws whatever a FrameSource
+// hands it, each over the last, so still files, animations, FIFOs and watched
+// directories all go through the same loop, and so can sources of your own.
+use opencv::{
+    core,
+    prelude::*,
+    Result,
+};
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+use crate::stream;
+use crate::watch::DirWatch;
+
+pub trait FrameSource {
+    // Block until the next image is due and return it, None once there are
+    // no more. Unreadable images along the way are skipped where the source
+    // can carry on without them, an error means it can't.
+    fn next_frame(&mut self) -> Option<Result<Mat>>;
+
+    // The file the last image came from, for the title. None for streams.
+    fn path(&self) -> Option<String> {
+        return None;
+    }
+}
+
+fn error(message: String) -> opencv::Error {
+    return opencv::Error::new(core::StsError, message);
+}
+
+// One image file, read again every `refresh` if given to pick up changes
+pub struct Still<L> {
+    path: String,
+    refresh: Option<Duration>,
+    load: L,
+    read: bool,
+}
+
+impl<L: Fn(&str) -> Result<Mat>> Still<L> {
+    pub fn new(path: &str, refresh: Option<Duration>, load: L) -> Still<L> {
+        return Still { path: path.to_string(), refresh, load, read: false };
+    }
+}
+
+impl<L: Fn(&str) -> Result<Mat>> FrameSource for Still<L> {
+    fn next_frame(&mut self) -> Option<Result<Mat>> {
+        if !self.read {
+            self.read = true;
+            return Some((self.load)(&self.path));
+        }
+        let interval = self.refresh?;
+        loop {
+            thread::sleep(interval);
+            // Keep the last good image if the file is missing or mid-write
+            match (self.load)(&self.path) {
+                Ok(image) if !image.empty() => return Some(Ok(image)),
+                _ => continue,
+            }
+        }
+    }
+
+    fn path(&self) -> Option<String> {
+        return Some(self.path.clone());
+    }
+}
+
+// Encoded images arriving on a FIFO, each one replaces the last
+pub struct Fifo<D> {
+    reader: stream::FrameReader,
+    decode: D,
+}
+
+impl<D: Fn(&[u8]) -> Result<Mat>> Fifo<D> {
+    pub fn new(reader: stream::FrameReader, decode: D) -> Fifo<D> {
+        return Fifo { reader, decode };
+    }
+}
+
+impl<D: Fn(&[u8]) -> Result<Mat>> FrameSource for Fifo<D> {
+    fn next_frame(&mut self) -> Option<Result<Mat>> {
+        loop {
+            let bytes = match self.reader.next_frame() {
+                Ok(bytes) => bytes,
+                Err(e) => return Some(Err(error(format!("Could not read the stream: {}", e)))),
+            };
+            match (self.decode)(&bytes) {
+                Ok(image) if !image.empty() => return Some(Ok(image)),
+                _ => eprintln!("Skipping a frame that isn't a readable image"),
+            }
+        }
+    }
+}
+
+// The newest image in a directory, then every newer one as it shows up
+pub(crate) struct Watch<L> {
+    watch: DirWatch,
+    pending: Option<PathBuf>,  // Already picked, not read yet
+    current: Option<PathBuf>,
+    load: L,
+}
+
+impl<L: Fn(&str) -> Result<Mat>> Watch<L> {
+    pub(crate) fn new(watch: DirWatch, first: PathBuf, load: L) -> Watch<L> {
+        return Watch { watch, pending: Some(first), current: None, load };
+    }
+}
+
+impl<L: Fn(&str) -> Result<Mat>> FrameSource for Watch<L> {
+    fn next_frame(&mut self) -> Option<Result<Mat>> {
+        loop {
+            let path = match self.pending.take().or_else(|| self.watch.next_image()) {
+                Some(path) => path,
+                None => return Some(Err(error("Stopped watching the directory".to_string()))),
+            };
+            // Keep the last image if the new one is still being written
+            match (self.load)(&path.to_string_lossy()) {
+                Ok(image) if !image.empty() => {
+                    self.current = Some(path);
+                    return Some(Ok(image));
+                },
+                _ => continue,
+            }
+        }
+    }
+
+    fn path(&self) -> Option<String> {
+        return self.current.as_ref().map(|path| path.to_string_lossy().into_owned());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Options;
+
+    #[test]
+    fn stills_without_refresh_run_dry() {
+        let mut still = Still::new("shot.png", None, |_: &str| Ok(Mat::default()));
+        assert!(matches!(still.next_frame(), Some(Ok(_))));
+        assert!(still.next_frame().is_none());
+        assert_eq!(still.path().as_deref(), Some("shot.png"));
+    }
+
+    // Hands out a fixed list of results
+    struct Scripted(std::vec::IntoIter<Result<Mat>>);
+
+    impl FrameSource for Scripted {
+        fn next_frame(&mut self) -> Option<Result<Mat>> {
+            return self.0.next();
+        }
+    }
+
+    #[test]
+    fn render_frames_until_the_source_stops() {
+        let image = || Mat::new_rows_cols_with_default(2, 2, core::CV_8UC3, core::Scalar::all(128.0));
+        // One cell is too small for a picture, it only takes the average color
+        let mut source = Scripted(vec![image(), image()].into_iter());
+        let mut drawn = 0;
+        crate::render_frames(&mut source, (1, 1), &Options::default(), |_| drawn += 1).unwrap();
+        assert_eq!(drawn, 2);
+
+        // Nothing after the error is rendered
+        let mut source = Scripted(vec![image(), Err(error("gone".to_string())), image()].into_iter());
+        let mut drawn = 0;
+        assert!(crate::render_frames(&mut source, (1, 1), &Options::default(), |_| drawn += 1).is_err());
+        assert_eq!(drawn, 1);
+    }
+}
@@ -0,0 +1,131 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read};
+use std::os::unix::fs::FileTypeExt;
+
+// Refuse to allocate for frames bigger than this, the stream is probably garbage
+const MAX_FRAME_BYTES: usize = 256 * 1024 * 1024;
+
+// How successive images are separated in the stream
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Framing {
+    #[default]
+    Length,  // 4 byte big endian length, then the encoded image
+    Lines,   // One base64 encoded image per line
+}
+
+impl Framing {
+    pub fn parse(value: &str) -> Result<Framing, String> {
+        match value {
+            "length" => Ok(Framing::Length),
+            "lines" => Ok(Framing::Lines),
+            _ => Err(format!("Invalid framing: {} (expected length or lines)", value)),
+        }
+    }
+}
+
+pub fn is_fifo(path: &str) -> bool {
+    return fs::metadata(path).is_ok_and(|meta| meta.file_type().is_fifo());
+}
+
+// Reads whole encoded images from a FIFO. When the writer goes away the
+// FIFO is reopened, which blocks until the next writer shows up.
+pub struct FrameReader {
+    path: String,
+    framing: Framing,
+    reader: Option<BufReader<File>>,
+}
+
+impl FrameReader {
+    pub fn new(path: &str, framing: Framing) -> FrameReader {
+        return FrameReader { path: path.to_string(), framing, reader: None };
+    }
+
+    // Block until the next complete frame arrives
+    pub fn next_frame(&mut self) -> io::Result<Vec<u8>> {
+        loop {
+            if self.reader.is_none() {
+                self.reader = Some(BufReader::new(File::open(&self.path)?));
+            }
+            let reader = self.reader.as_mut().unwrap();
+            let frame = match self.framing {
+                Framing::Length => read_length_prefixed(reader),
+                Framing::Lines => read_base64_line(reader),
+            };
+            match frame {
+                Ok(Some(frame)) => return Ok(frame),
+                // EOF, a partial frame at the end is dropped
+                Ok(None) => self.reader = None,
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => self.reader = None,
+                Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                    // Lost track of the framing, start over with the next writer
+                    eprintln!("Bad frame in {}: {}", self.path, e);
+                    self.reader = None;
+                },
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+// Ok(None) on a clean EOF between frames
+fn read_length_prefixed(reader: &mut impl Read) -> io::Result<Option<Vec<u8>>> {
+    let mut header = [0u8; 4];
+    let mut filled = 0;
+    while filled < header.len() {
+        match reader.read(&mut header[filled..])? {
+            0 if filled == 0 => return Ok(None),
+            0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+            n => filled += n,
+        }
+    }
+    let length = u32::from_be_bytes(header) as usize;
+    if length > MAX_FRAME_BYTES {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("frame of {} bytes is too large", length)));
+    }
+    let mut frame = vec![0u8; length];
+    reader.read_exact(&mut frame)?;
+    return Ok(Some(frame));
+}
+
+// Ok(None) on EOF, blank lines are skipped
+fn read_base64_line(reader: &mut impl BufRead) -> io::Result<Option<Vec<u8>>> {
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let encoded = line.trim();
+        if encoded.is_empty() {
+            continue;
+        }
+        return STANDARD
+            .decode(encoded)
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn length_prefixed_frames() {
+        let mut stream: &[u8] = &[0, 0, 0, 2, 7, 8, 0, 0, 0, 1, 9, 0, 0];
+        assert_eq!(read_length_prefixed(&mut stream).unwrap(), Some(vec![7, 8]));
+        assert_eq!(read_length_prefixed(&mut stream).unwrap(), Some(vec![9]));
+        // Header cut short by EOF
+        assert_eq!(read_length_prefixed(&mut stream).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(read_length_prefixed(&mut stream).unwrap(), None);
+    }
+
+    #[test]
+    fn base64_lines() {
+        let mut stream: &[u8] = b"aGk=\n\nYnll\n";
+        assert_eq!(read_base64_line(&mut stream).unwrap(), Some(b"hi".to_vec()));
+        assert_eq!(read_base64_line(&mut stream).unwrap(), Some(b"bye".to_vec()));
+        assert_eq!(read_base64_line(&mut stream).unwrap(), None);
+    }
+}
@@ -0,0 +1,89 @@
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::{Duration, SystemTime};
+
+// How long the directory has to stay quiet before we pick the newest file,
+// so a burst of uploads (or one file written in chunks) draws once
+const SETTLE_TIME: Duration = Duration::from_millis(250);
+
+// Follows the most recently modified image in a directory
+pub struct DirWatch {
+    dir: PathBuf,
+    _watcher: RecommendedWatcher,  // Stops watching when dropped
+    events: Receiver<notify::Result<notify::Event>>,
+    shown: Option<(PathBuf, SystemTime)>,
+}
+
+impl DirWatch {
+    pub fn new(dir: &str) -> notify::Result<DirWatch> {
+        let (sender, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender)?;
+        watcher.watch(Path::new(dir), RecursiveMode::NonRecursive)?;
+        return Ok(DirWatch { dir: PathBuf::from(dir), _watcher: watcher, events, shown: None });
+    }
+
+    // The newest image right now, if it hasn't been handed out already
+    pub fn newest(&mut self) -> Option<PathBuf> {
+        let images = crate::list_images(&self.dir).unwrap_or_default();
+        let candidates: Vec<(PathBuf, SystemTime)> = images
+            .into_iter()
+            .filter_map(|path| {
+                let modified = path.metadata().and_then(|m| m.modified()).ok()?;
+                Some((path, modified))
+            })
+            .collect();
+        let newest = pick_newer(&candidates, self.shown.as_ref())?;
+        self.shown = Some(newest.clone());
+        return Some(newest.0);
+    }
+
+    // Block until a newer image shows up. None if the watch broke down.
+    pub fn next_image(&mut self) -> Option<PathBuf> {
+        loop {
+            // Anything happening in the directory is worth a look
+            if self.events.recv().is_err() {
+                return None;
+            }
+            loop {
+                match self.events.recv_timeout(SETTLE_TIME) {
+                    Ok(_) => continue,
+                    Err(RecvTimeoutError::Timeout) => break,
+                    Err(RecvTimeoutError::Disconnected) => return None,
+                }
+            }
+            if let Some(path) = self.newest() {
+                return Some(path);
+            }
+        }
+    }
+}
+
+// The most recently modified candidate, as long as it's a different file or
+// a later write than the one already shown
+fn pick_newer(candidates: &[(PathBuf, SystemTime)], shown: Option<&(PathBuf, SystemTime)>) -> Option<(PathBuf, SystemTime)> {
+    // Ties go to the later name, like numbered screenshots
+    let newest = candidates.iter().max_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.cmp(&b.0)))?;
+    if shown == Some(newest) {
+        return None;
+    }
+    return Some(newest.clone());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn newest_file_wins_once() {
+        let at = |secs: u64| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+        let candidates = vec![(PathBuf::from("a.png"), at(10)), (PathBuf::from("b.png"), at(30)), (PathBuf::from("c.png"), at(20))];
+        let newest = pick_newer(&candidates, None).unwrap();
+        assert_eq!(newest.0, PathBuf::from("b.png"));
+        assert_eq!(pick_newer(&candidates, Some(&newest)), None);
+        // Rewriting the same file counts as new
+        assert_eq!(pick_newer(&[(PathBuf::from("b.png"), at(40))], Some(&newest)).map(|n| n.0), Some(PathBuf::from("b.png")));
+        assert_eq!(pick_newer(&[(PathBuf::from("x.png"), at(5)), (PathBuf::from("y.png"), at(5))], None).map(|n| n.0), Some(PathBuf::from("y.png")));
+        assert_eq!(pick_newer(&[], None), None);
+    }
+}